@@ -0,0 +1,149 @@
+//! Benchmarks of each stage of the pipeline, and of the whole of it. Run with
+//! `cargo bench --features bench`.
+//!
+//! Stages running the models are skipped unless the landmark model is in
+//! `resources/` (see `make fetch-models`) or in the model directory.
+
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use ciya_lib::{
+    ciyafier::{Ciyafier, ControlPoints, Emotion, Point},
+    detectors::{
+        bench::{argmax, detect_faces, detect_landmarks, face_to_nn_input},
+        MouthDetectorTrait,
+        WeebDetector,
+    },
+    models::{ModelManager, FACE_MODEL, LANDMARK_MODEL},
+    projector::Projector,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use image::{imageops::FilterType, io::Reader as ImageReader, DynamicImage, ImageFormat};
+use ndarray::{Array2, Array3};
+
+const TEST_IMAGE: &[u8] = include_bytes!("../tests/test.png");
+
+// Where the mouth is in `test.png`.
+const MOUTH: ControlPoints<f32> = ControlPoints::new(
+    Point::new(190., 318.),
+    Point::new(212., 306.),
+    Point::new(236., 316.),
+    Point::new(213., 330.),
+);
+
+fn test_image() -> DynamicImage {
+    ImageReader::with_format(Cursor::new(TEST_IMAGE), ImageFormat::Png)
+        .decode()
+        .unwrap()
+}
+
+fn detector() -> Option<WeebDetector<'static>> {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let face_model = resources.join(FACE_MODEL.file_name);
+    let mut landmark_model: PathBuf = resources.join(LANDMARK_MODEL.file_name);
+    if !landmark_model.is_file() {
+        let manager = ModelManager::with_default_root().ok()?.offline(true);
+        landmark_model = manager.ensure(&LANDMARK_MODEL).ok()?;
+    }
+    WeebDetector::new(face_model, landmark_model).ok()
+}
+
+fn bench_preprocessing(c: &mut Criterion) {
+    let face = Array3::from_shape_fn((3, 128, 128), |(c, y, x)| {
+        ((c * 7 + y * 3 + x) % 256) as f32
+    });
+    c.bench_function("face_to_nn_input", |b| {
+        b.iter_batched(|| face.clone(), face_to_nn_input, BatchSize::SmallInput);
+    });
+
+    let heatmap = Array2::from_shape_fn((64, 64), |(y, x)| ((y * 64 + x) * 37 % 4096) as f32);
+    c.bench_function("argmax", |b| b.iter(|| argmax(&heatmap.view())));
+}
+
+fn bench_models(c: &mut Criterion) {
+    let detector = match detector() {
+        Some(detector) => detector,
+        None => {
+            eprintln!("landmark model not available, skipping model benchmarks");
+            return;
+        }
+    };
+    let image = test_image().into_rgb8();
+
+    c.bench_function("cascade_detection", |b| {
+        b.iter(|| detect_faces(&detector, &image).unwrap());
+    });
+    let faces = detect_faces(&detector, &image).unwrap();
+    if let Some(face) = faces.first() {
+        c.bench_function("landmark_inference", |b| {
+            b.iter(|| detect_landmarks(&detector, &image, face).unwrap());
+        });
+    }
+}
+
+fn bench_projection(c: &mut Criterion) {
+    let projector = Projector::new();
+    let image = test_image();
+    // the whole projection: control points, warp and overlay
+    let mut group = c.benchmark_group("project");
+    for antialias_scale in 1..=8 {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(antialias_scale),
+            &antialias_scale,
+            |b, &antialias_scale| {
+                b.iter_batched(
+                    || image.clone(),
+                    |image| {
+                        projector
+                            .project(image, MOUTH, Emotion::Auto, antialias_scale)
+                            .unwrap()
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_end_to_end(c: &mut Criterion) {
+    let detector = match detector() {
+        Some(detector) => detector,
+        None => {
+            eprintln!("landmark model not available, skipping end-to-end benchmarks");
+            return;
+        }
+    };
+    let ciyafier = Ciyafier::new(Box::new(detector));
+    let image = test_image();
+
+    let mut group = c.benchmark_group("ciya");
+    group.sample_size(10);
+    for width in [256, 512, 1024, 2048] {
+        let height = image.height() * width / image.width();
+        let image = image.resize_exact(width, height, FilterType::Lanczos3);
+        // the face may be lost at small sizes
+        if ciyafier.detect(&image).is_err() {
+            continue;
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(width), &image, |b, image| {
+            b.iter_batched(
+                || image.clone(),
+                |image| ciyafier.ciya(image, Emotion::Auto, 8).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_preprocessing,
+    bench_models,
+    bench_projection,
+    bench_end_to_end
+);
+criterion_main!(benches);
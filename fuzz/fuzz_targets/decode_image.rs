@@ -0,0 +1,8 @@
+#![no_main]
+
+use ciya_lib::codec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = codec::decode(data);
+});
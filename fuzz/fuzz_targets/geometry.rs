@@ -0,0 +1,41 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use ciya_lib::{
+    ciyafier::{ControlPoints, Emotion, Point},
+    projector::Projector,
+};
+use image::{DynamicImage, RgbaImage};
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+
+lazy_static! {
+    // decoding the ciya on every run would dominate the time spent
+    static ref PROJECTOR: Projector = Projector::new();
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    points: [(f32, f32); 4],
+    scale: f32,
+    antialias_scale: u8,
+}
+
+fuzz_target!(|input: Input| {
+    let [p1, p2, p3, p4] = input.points.map(|(x, y)| Point::new(x, y));
+    let mouth = ControlPoints::new(p1, p2, p3, p4);
+
+    let _ = mouth.is_convex();
+    let _ = mouth.enlarge(input.scale, false);
+    let _ = mouth.enlarge(input.scale, true);
+    let _ = mouth.centralize_y();
+    let _ = mouth.shift_origin();
+
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(64, 64));
+    let _ = PROJECTOR.project(
+        image,
+        mouth,
+        Emotion::Auto,
+        u32::from(input.antialias_scale % 9),
+    );
+});
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::{
+    commands::CliEmotion,
+    i18n::{Language, Text},
+    media::replace_file,
+    pipeline::{ciyaify_file, CiyaError, Origin},
+    settings::Settings,
+    state::{Render, State},
+    store::{ResultKey, Store},
+};
+
+const ANTIALIAS_SCALES: [u32; 3] = [1, 4, 8];
+
+#[derive(Debug, Copy, Clone)]
+enum Action {
+    Emotion(CliEmotion),
+    Flip,
+    AntialiasScale(u32),
+}
+
+impl Action {
+    fn parse(data: &str) -> Option<Self> {
+        match data.split_once(':')? {
+            ("emotion", "smile") => Some(Self::Emotion(CliEmotion::Smile)),
+            ("emotion", "cry") => Some(Self::Emotion(CliEmotion::Cry)),
+            ("emotion", "auto") => Some(Self::Emotion(CliEmotion::Auto)),
+            ("emotion", "flip") => Some(Self::Flip),
+            ("aa", scale) => scale.parse().ok().map(Self::AntialiasScale),
+            _ => None,
+        }
+    }
+
+    fn apply(self, settings: &Settings) -> Settings {
+        let mut settings = settings.clone();
+        match self {
+            Self::Emotion(emotion) => settings.emotion = emotion,
+            Self::Flip => {
+                settings.emotion = match settings.emotion {
+                    CliEmotion::Auto => CliEmotion::Flip,
+                    CliEmotion::Flip => CliEmotion::Auto,
+                    CliEmotion::Smile => CliEmotion::Cry,
+                    CliEmotion::Cry => CliEmotion::Smile,
+                }
+            }
+            Self::AntialiasScale(scale) => settings.antialias_scale = scale,
+        }
+        settings
+    }
+}
+
+pub fn keyboard(max_antialias_scale: u32, lang: Language) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([
+        vec![
+            InlineKeyboardButton::callback(Text::Smile.localize(lang), "emotion:smile"),
+            InlineKeyboardButton::callback(Text::Cry.localize(lang), "emotion:cry"),
+            InlineKeyboardButton::callback(Text::Auto.localize(lang), "emotion:auto"),
+            InlineKeyboardButton::callback(Text::Flip.localize(lang), "emotion:flip"),
+        ],
+        ANTIALIAS_SCALES
+            .iter()
+            .filter(|scale| **scale <= max_antialias_scale)
+            .map(|scale| {
+                InlineKeyboardButton::callback(format!("AA ×{}", scale), format!("aa:{}", scale))
+            })
+            .collect(),
+    ])
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        user = query.from.id.0,
+        chat = query.message.as_ref().map(|msg| msg.chat.id.0),
+        message = query.message.as_ref().map(|msg| msg.id.0),
+    )
+)]
+pub async fn answer_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    state: Arc<State>,
+    store: Arc<Store>,
+) -> ResponseResult<()> {
+    let (message, action) = match (
+        query.message.as_ref(),
+        query.data.as_deref().and_then(Action::parse),
+    ) {
+        (Some(message), Some(action)) => (message, action),
+        _ => {
+            bot.answer_callback_query(query.id).await?;
+            return Ok(());
+        }
+    };
+    let lang = Language::resolve(store.settings(message.chat.id).language, Some(&query.from));
+    let render = match state.render(message.chat.id, message.id) {
+        Some(render) => render,
+        None => {
+            bot.answer_callback_query(query.id)
+                .text(Text::ResultExpired.localize(lang))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    if render.requester.map_or(false, |user| user != query.from.id) {
+        bot.answer_callback_query(query.id)
+            .text(Text::NotRequester.localize(lang))
+            .await?;
+        return Ok(());
+    }
+
+    let settings = action.apply(&render.settings);
+    let origin = Origin {
+        user: Some(query.from.id),
+        chat: Some(message.chat.id),
+        language: lang,
+    };
+    let kind = render.source.kind.resolve(settings.output);
+    match ciyaify_file(
+        &bot,
+        &state,
+        &store,
+        origin,
+        &render.source,
+        &settings,
+        kind,
+    )
+    .await
+    {
+        Ok(file) => {
+            let sent = replace_file(
+                &bot,
+                message,
+                file,
+                kind,
+                keyboard(state.config.max_antialias_scale, lang),
+            )
+            .await?;
+            store.cache_result(&ResultKey::new(&render.source, &settings, kind), &sent);
+            state.forget_render(message.chat.id, message.id);
+            state.remember_render(
+                sent.chat.id,
+                sent.id,
+                Render {
+                    source: render.source,
+                    settings,
+                    requester: render.requester,
+                },
+            );
+            bot.answer_callback_query(query.id).await?;
+        }
+        Err(CiyaError::Request(err)) => return Err(err),
+        Err(err) => {
+            bot.answer_callback_query(query.id)
+                .text(err.localize(lang))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
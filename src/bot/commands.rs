@@ -2,9 +2,12 @@ use std::str::FromStr;
 
 use ciya_lib::ciyafier::Emotion;
 use clap::{ColorChoice, Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
 use teloxide::{macros::BotCommands, utils::command::ParseError};
 use thiserror::Error;
 
+use crate::i18n::Language;
+
 #[derive(Error, Debug)]
 pub enum FormatError {
     #[error("shell-like parse error")]
@@ -13,17 +16,18 @@ pub enum FormatError {
     ClapError(#[from] clap::Error),
 }
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
+#[derive(Debug, Copy, Clone, ValueEnum, Serialize, Deserialize)]
 pub enum Mode {
     Weeb,
     Standard,
 }
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, ValueEnum, Serialize, Deserialize)]
 pub enum CliEmotion {
     Auto,
     Smile,
     Cry,
+    Flip,
 }
 
 impl From<CliEmotion> for Emotion {
@@ -32,21 +36,65 @@ impl From<CliEmotion> for Emotion {
             CliEmotion::Auto => Self::Auto,
             CliEmotion::Smile => Self::Smile,
             CliEmotion::Cry => Self::Cry,
+            CliEmotion::Flip => Self::Flip,
         }
     }
 }
 
-#[derive(Debug, Clone, Parser)]
+#[derive(Debug, Copy, Clone, ValueEnum, Serialize, Deserialize)]
+pub enum OutputType {
+    /// Mirror the medium of the source image.
+    Auto,
+    Photo,
+    Document,
+    Sticker,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
 #[command(name = "ciya-bot")]
 #[command(author, version, about)]
 #[command(no_binary_name = true, color = ColorChoice::Never, disable_help_flag = true)]
 pub struct Opt {
-    #[arg(value_enum, default_value_t = CliEmotion::Auto)]
-    pub emotion: CliEmotion,
-    #[arg(value_enum, default_value_t = Mode::Weeb)]
-    pub mode: Mode,
-    #[arg(default_value_t = 8)]
-    pub antialias_scale: u32,
+    /// Defaults to the chat settings, or auto.
+    #[arg(value_enum)]
+    pub emotion: Option<CliEmotion>,
+    /// Defaults to the chat settings, or weeb.
+    #[arg(value_enum)]
+    pub mode: Option<Mode>,
+    /// Defaults to the chat settings, or 8.
+    pub antialias_scale: Option<u32>,
+    /// Defaults to the chat settings, or auto.
+    #[arg(short, long, value_enum)]
+    pub output: Option<OutputType>,
+    /// Defaults to the chat settings, or the language of your Telegram client.
+    #[arg(short, long, value_enum)]
+    pub language: Option<Language>,
+}
+
+impl Opt {
+    // Options in the caption of an image. Captions are mostly ordinary text,
+    // so parse errors are only reported if the caption starts like options.
+    pub fn from_caption(caption: &str) -> Result<Self, anyhow::Error> {
+        Self::from_str(caption).or_else(|e| {
+            let first = caption.split_whitespace().next().unwrap_or_default();
+            let attempted = first.starts_with('-')
+                || <CliEmotion as ValueEnum>::from_str(first, true).is_ok()
+                || <Mode as ValueEnum>::from_str(first, true).is_ok();
+            if attempted {
+                Err(e)
+            } else {
+                Ok(Self::default())
+            }
+        })
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.emotion.is_none()
+            && self.mode.is_none()
+            && self.antialias_scale.is_none()
+            && self.output.is_none()
+            && self.language.is_none()
+    }
 }
 
 impl FromStr for Opt {
@@ -74,8 +122,45 @@ pub enum Commands {
     parse_with = parse_opt
     )]
     Ciyaify(Result<Opt, String>),
+    #[command(
+    description = "show or change default options of this chat.",
+    parse_with = parse_opt
+    )]
+    Settings(Result<Opt, String>),
+    #[command(description = "reset default options of this chat.")]
+    ResetSettings,
     #[command(description = "help of this bot.")]
     Help,
     #[command(description = "help of this bot.")]
     Start,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CliEmotion, Opt};
+
+    #[test]
+    fn ordinary_captions_are_ignored() {
+        for caption in ["", "my cat", "lol", "it's me", "4 cats"] {
+            assert!(
+                Opt::from_caption(caption).unwrap().is_empty(),
+                "{}",
+                caption
+            );
+        }
+    }
+
+    #[test]
+    fn captions_with_options_are_parsed() {
+        let opt = Opt::from_caption("cry weeb 4 -o sticker").unwrap();
+        assert_eq!(opt.emotion, Some(CliEmotion::Cry));
+        assert_eq!(opt.antialias_scale, Some(4));
+    }
+
+    #[test]
+    fn invalid_options_are_reported() {
+        for caption in ["smile weeb many", "--output gif", "Cry please"] {
+            assert!(Opt::from_caption(caption).is_err(), "{}", caption);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+use tracing_subscriber::EnvFilter;
+
+const CONFIG_ENV: &str = "CIYA_BOT_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "ciya_bot.toml";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("expected text or json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Log filter in `tracing_subscriber::EnvFilter` syntax, e.g. `info` or
+    /// `info,ciya_lib=debug`.
+    pub log_level: String,
+    /// Whether logs are printed as human-readable text or as JSON lines.
+    pub log_format: LogFormat,
+    /// Path of the sled database storing chat settings.
+    pub database: PathBuf,
+    /// Chat to upload inline results to. Inline mode is disabled if unset.
+    pub cache_chat: Option<i64>,
+    /// Chats allowed to use the bot. Everyone is allowed if empty.
+    pub allowed_chats: Vec<i64>,
+    /// Maximum width or height of source images, in pixels.
+    pub max_dimension: u32,
+    /// Maximum size of source files, in bytes.
+    pub max_file_size: u32,
+    /// Maximum `antialias_scale` users may request.
+    pub max_antialias_scale: u32,
+    /// How many images may be processed at the same time.
+    pub concurrency: usize,
+    /// How many images may wait for processing before new ones are rejected.
+    pub queue_size: usize,
+    /// Requests allowed per user per minute. Zero disables the limit.
+    pub user_rate_limit: usize,
+    /// Requests allowed per chat per minute. Zero disables the limit.
+    pub chat_rate_limit: usize,
+    /// Path of the face detection model. Downloaded if unset.
+    pub face_model: Option<PathBuf>,
+    /// Path of the landmark detection model. Downloaded if unset.
+    pub landmark_model: Option<PathBuf>,
+    /// Directory to download models to. Defaults to `$CIYA_MODEL_DIR`, or the
+    /// local data directory.
+    pub model_dir: Option<PathBuf>,
+    /// Fail instead of downloading missing models.
+    pub offline: bool,
+    /// Base URLs to download models from before trying upstream.
+    pub model_mirrors: Vec<String>,
+    /// Proxy to download models through. Defaults to `$HTTPS_PROXY`.
+    pub model_proxy: Option<String>,
+    /// Bot API server to talk to. Defaults to the official one.
+    pub api_url: Option<String>,
+    /// Public URL Telegram delivers updates to. Long polling is used if unset.
+    pub webhook_url: Option<String>,
+    /// Address the webhook server listens on.
+    pub listen: SocketAddr,
+    /// Path the webhook server accepts updates on. Defaults to the path of
+    /// `webhook_url`.
+    pub webhook_path: Option<String>,
+    /// Secret Telegram sends along with every update, to authenticate the
+    /// webhook.
+    pub webhook_secret: Option<String>,
+    /// Address serving `/metrics` and `/healthz`. Disabled if unset.
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log_level: String::from("info"),
+            log_format: LogFormat::Text,
+            database: dirs::data_local_dir()
+                .unwrap_or_default()
+                .join("ciya-rs")
+                .join("bot.sled"),
+            cache_chat: None,
+            allowed_chats: vec![],
+            max_dimension: 4096,
+            max_file_size: 20 * 1024 * 1024,
+            max_antialias_scale: 8,
+            concurrency: 1,
+            queue_size: 16,
+            user_rate_limit: 10,
+            chat_rate_limit: 30,
+            face_model: None,
+            landmark_model: None,
+            model_dir: None,
+            offline: false,
+            model_mirrors: vec![],
+            model_proxy: None,
+            api_url: None,
+            webhook_url: None,
+            listen: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            webhook_path: None,
+            webhook_secret: None,
+            metrics_listen: None,
+        }
+    }
+}
+
+fn override_with<T>(name: &str, target: &mut T) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env::var(name) {
+        *target = value
+            .parse()
+            .map_err(|e| anyhow!("invalid value of {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn override_option_with<T>(name: &str, target: &mut Option<T>) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env::var(name) {
+        *target = if value.is_empty() {
+            None
+        } else {
+            Some(
+                value
+                    .parse()
+                    .map_err(|e| anyhow!("invalid value of {}: {}", name, e))?,
+            )
+        };
+    }
+    Ok(())
+}
+
+impl Config {
+    // Read the config file, then apply overrides from environment variables.
+    pub fn load() -> Result<Self> {
+        let path = env::var_os(CONFIG_ENV).map(PathBuf::from);
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).is_file() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("unable to read config file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        override_with("CIYA_BOT_LOG_LEVEL", &mut self.log_level)?;
+        override_with("CIYA_BOT_LOG_FORMAT", &mut self.log_format)?;
+        override_with("CIYA_BOT_DB", &mut self.database)?;
+        override_option_with("CIYA_BOT_CACHE_CHAT", &mut self.cache_chat)?;
+        if let Ok(chats) = env::var("CIYA_BOT_ALLOWED_CHATS") {
+            self.allowed_chats = chats
+                .split(',')
+                .map(str::trim)
+                .filter(|chat| !chat.is_empty())
+                .map(|chat| {
+                    chat.parse()
+                        .map_err(|e| anyhow!("invalid value of CIYA_BOT_ALLOWED_CHATS: {}", e))
+                })
+                .collect::<Result<_>>()?;
+        }
+        override_with("CIYA_BOT_MAX_DIMENSION", &mut self.max_dimension)?;
+        override_with("CIYA_BOT_MAX_FILE_SIZE", &mut self.max_file_size)?;
+        override_with(
+            "CIYA_BOT_MAX_ANTIALIAS_SCALE",
+            &mut self.max_antialias_scale,
+        )?;
+        override_with("CIYA_BOT_CONCURRENCY", &mut self.concurrency)?;
+        override_with("CIYA_BOT_QUEUE_SIZE", &mut self.queue_size)?;
+        override_with("CIYA_BOT_USER_RATE_LIMIT", &mut self.user_rate_limit)?;
+        override_with("CIYA_BOT_CHAT_RATE_LIMIT", &mut self.chat_rate_limit)?;
+        override_option_with("CIYA_BOT_FACE_MODEL", &mut self.face_model)?;
+        override_option_with("CIYA_BOT_LANDMARK_MODEL", &mut self.landmark_model)?;
+        override_option_with("CIYA_BOT_MODEL_DIR", &mut self.model_dir)?;
+        override_with("CIYA_BOT_OFFLINE", &mut self.offline)?;
+        if let Ok(mirrors) = env::var("CIYA_BOT_MODEL_MIRRORS") {
+            self.model_mirrors = mirrors
+                .split(',')
+                .map(str::trim)
+                .filter(|mirror| !mirror.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        override_option_with("CIYA_BOT_MODEL_PROXY", &mut self.model_proxy)?;
+        override_option_with("CIYA_BOT_API_URL", &mut self.api_url)?;
+        override_option_with("CIYA_BOT_WEBHOOK_URL", &mut self.webhook_url)?;
+        override_with("CIYA_BOT_LISTEN", &mut self.listen)?;
+        override_option_with("CIYA_BOT_WEBHOOK_PATH", &mut self.webhook_path)?;
+        override_option_with("CIYA_BOT_WEBHOOK_SECRET", &mut self.webhook_secret)?;
+        override_option_with("CIYA_BOT_METRICS_LISTEN", &mut self.metrics_listen)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        EnvFilter::try_new(&self.log_level).context("invalid log_level")?;
+        if self.max_dimension == 0 {
+            bail!("max_dimension must be positive");
+        }
+        if self.max_file_size == 0 {
+            bail!("max_file_size must be positive");
+        }
+        if self.max_antialias_scale == 0 {
+            bail!("max_antialias_scale must be positive");
+        }
+        if self.concurrency == 0 {
+            bail!("concurrency must be positive");
+        }
+        match (&self.face_model, &self.landmark_model) {
+            (None, None) => {}
+            (Some(face_model), Some(landmark_model)) => {
+                for model in [face_model, landmark_model] {
+                    if !model.is_file() {
+                        bail!("model {} doesn't exist", model.display());
+                    }
+                }
+            }
+            _ => bail!("face_model and landmark_model must be set together"),
+        }
+        for (name, url) in [
+            ("api_url", &self.api_url),
+            ("webhook_url", &self.webhook_url),
+            ("model_proxy", &self.model_proxy),
+        ] {
+            if let Some(url) = url {
+                Url::parse(url).with_context(|| format!("invalid {}", name))?;
+            }
+        }
+        for mirror in &self.model_mirrors {
+            Url::parse(mirror).with_context(|| format!("invalid model mirror {}", mirror))?;
+        }
+        if let Some(path) = &self.webhook_path {
+            if !path.starts_with('/') {
+                bail!("webhook_path must start with /");
+            }
+        }
+        if let Some(secret) = &self.webhook_secret {
+            // restrictions imposed by the Bot API
+            if !(1..=256).contains(&secret.len())
+                || !secret
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                bail!("webhook_secret must be 1-256 characters of A-Z, a-z, 0-9, _ and -");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cache_chat(&self) -> Option<ChatId> {
+        self.cache_chat.map(ChatId)
+    }
+
+    pub fn api_url(&self) -> Option<Url> {
+        self.api_url.as_deref().and_then(|url| Url::parse(url).ok())
+    }
+
+    pub fn webhook_url(&self) -> Option<Url> {
+        self.webhook_url
+            .as_deref()
+            .and_then(|url| Url::parse(url).ok())
+    }
+
+    pub fn is_allowed(&self, chat: ChatId) -> bool {
+        self.allowed_chats.is_empty() || self.allowed_chats.contains(&chat.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, sync::Mutex};
+
+    use lazy_static::lazy_static;
+
+    use super::{Config, LogFormat};
+
+    lazy_static! {
+        // the environment is shared by the tests running in parallel
+        static ref ENV: Mutex<()> = Mutex::new(());
+    }
+
+    // Apply `vars` on top of `config` as overrides, then validate it.
+    fn with_env(mut config: Config, vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+        let _guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let result = config.apply_env().and_then(|()| config.validate());
+        for (name, _) in vars {
+            env::remove_var(name);
+        }
+        result.map(|()| config)
+    }
+
+    #[test]
+    fn parses_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            log_level = "info,ciya_lib=debug"
+            log_format = "json"
+            cache_chat = -100
+            allowed_chats = [1, 2]
+            queue_size = 4
+            model_mirrors = ["https://mirror.example/models"]
+            listen = "127.0.0.1:8443"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.cache_chat, Some(-100));
+        assert_eq!(config.allowed_chats, [1, 2]);
+        assert_eq!(config.queue_size, 4);
+        assert_eq!(config.model_mirrors, ["https://mirror.example/models"]);
+        assert_eq!(config.listen, "127.0.0.1:8443".parse().unwrap());
+        // unset ones keep their defaults
+        assert_eq!(config.max_antialias_scale, 8);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("max_dimensions = 1024").is_err());
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let config = Config {
+            cache_chat: Some(-100),
+            max_dimension: 2048,
+            ..Config::default()
+        };
+        let config = with_env(
+            config,
+            &[
+                ("CIYA_BOT_MAX_DIMENSION", "1024"),
+                ("CIYA_BOT_LOG_FORMAT", "json"),
+                ("CIYA_BOT_CACHE_CHAT", ""),
+                ("CIYA_BOT_ALLOWED_CHATS", "1, 2,"),
+                ("CIYA_BOT_MODEL_DIR", "/var/lib/ciya"),
+                (
+                    "CIYA_BOT_MODEL_MIRRORS",
+                    " https://a.example/models , ,https://b.example/",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.max_dimension, 1024);
+        assert_eq!(config.log_format, LogFormat::Json);
+        // empty values unset options
+        assert_eq!(config.cache_chat, None);
+        assert_eq!(config.allowed_chats, [1, 2]);
+        assert_eq!(config.model_dir, Some(PathBuf::from("/var/lib/ciya")));
+        assert_eq!(
+            config.model_mirrors,
+            ["https://a.example/models", "https://b.example/"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_env() {
+        for (name, value) in [
+            ("CIYA_BOT_QUEUE_SIZE", "many"),
+            ("CIYA_BOT_LOG_FORMAT", "yaml"),
+            ("CIYA_BOT_ALLOWED_CHATS", "1,two"),
+            ("CIYA_BOT_OFFLINE", "yes"),
+        ] {
+            let e = with_env(Config::default(), &[(name, value)]).unwrap_err();
+            assert!(e.to_string().contains(name), "{}: {}", name, e);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let invalid = [
+            Config {
+                log_level: String::from("info,ciya_lib=loud"),
+                ..Config::default()
+            },
+            Config {
+                concurrency: 0,
+                ..Config::default()
+            },
+            Config {
+                max_antialias_scale: 0,
+                ..Config::default()
+            },
+            Config {
+                face_model: Some(PathBuf::from("Cargo.toml")),
+                ..Config::default()
+            },
+            Config {
+                face_model: Some(PathBuf::from("missing.xml")),
+                landmark_model: Some(PathBuf::from("missing.onnx")),
+                ..Config::default()
+            },
+            Config {
+                model_mirrors: vec![String::from("not a url")],
+                ..Config::default()
+            },
+            Config {
+                webhook_path: Some(String::from("webhook")),
+                ..Config::default()
+            },
+            Config {
+                webhook_secret: Some(String::from("no spaces")),
+                ..Config::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+        Config::default().validate().unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use clap::{CommandFactory, ValueEnum};
+use serde::{Deserialize, Serialize};
+use teloxide::types::User;
+
+use crate::{commands::Opt, pipeline::CiyaError};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum Language {
+    En,
+    Zh,
+    Ja,
+}
+
+impl Language {
+    // Pick a language from an IETF language tag such as `en-US` or `zh-hans`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(|c| c == '-' || c == '_').next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "zh" => Some(Self::Zh),
+            "ja" => Some(Self::Ja),
+            _ => None,
+        }
+    }
+
+    // The language set for the chat, or else the one of the user's client.
+    pub fn resolve(setting: Option<Self>, user: Option<&User>) -> Self {
+        setting
+            .or_else(|| {
+                user.and_then(|user| user.language_code.as_deref())
+                    .and_then(Self::from_code)
+            })
+            .unwrap_or(Self::En)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Text {
+    ReplyToImage,
+    ResultExpired,
+    NotRequester,
+    InlineUnavailable,
+    SendImageFirst,
+    AdminOnly,
+    SettingsUpdated,
+    SettingsNotSaved,
+    ImageFailed(usize),
+    QueuePosition(usize),
+    Smile,
+    Cry,
+    Auto,
+    Flip,
+}
+
+impl Text {
+    pub fn localize(self, lang: Language) -> String {
+        use Language::{En, Ja, Zh};
+        match (self, lang) {
+            (Self::ReplyToImage, En) => {
+                "Please reply to the image or album you want to ciyaify.".into()
+            }
+            (Self::ReplyToImage, Zh) => "请回复你想要 ciyaify 的图片或相册。".into(),
+            (Self::ReplyToImage, Ja) => {
+                "ciyaify したい画像またはアルバムに返信してください。".into()
+            }
+            (Self::ResultExpired, En) => "This result has expired, please ciyaify again.".into(),
+            (Self::ResultExpired, Zh) => "此结果已过期，请重新 ciyaify。".into(),
+            (Self::ResultExpired, Ja) => {
+                "この結果は期限切れです。もう一度 ciyaify してください。".into()
+            }
+            (Self::NotRequester, En) => {
+                "Only the one who asked for this result can change it.".into()
+            }
+            (Self::NotRequester, Zh) => "只有请求此结果的人可以更改它。".into(),
+            (Self::NotRequester, Ja) => "この結果を変更できるのはリクエストした人だけです。".into(),
+            (Self::InlineUnavailable, En) => "Inline mode is not available.".into(),
+            (Self::InlineUnavailable, Zh) => "内联模式不可用。".into(),
+            (Self::InlineUnavailable, Ja) => "インラインモードは利用できません。".into(),
+            (Self::SendImageFirst, En) => "Send me an image first.".into(),
+            (Self::SendImageFirst, Zh) => "请先发给我一张图片。".into(),
+            (Self::SendImageFirst, Ja) => "まず画像を送ってください。".into(),
+            (Self::AdminOnly, En) => "Only administrators can change settings of this chat.".into(),
+            (Self::AdminOnly, Zh) => "只有管理员可以更改此聊天的设置。".into(),
+            (Self::AdminOnly, Ja) => "このチャットの設定を変更できるのは管理者だけです。".into(),
+            (Self::SettingsUpdated, En) => "Settings updated.".into(),
+            (Self::SettingsUpdated, Zh) => "设置已更新。".into(),
+            (Self::SettingsUpdated, Ja) => "設定を更新しました。".into(),
+            (Self::SettingsNotSaved, En) => "Unable to save settings.".into(),
+            (Self::SettingsNotSaved, Zh) => "无法保存设置。".into(),
+            (Self::SettingsNotSaved, Ja) => "設定を保存できませんでした。".into(),
+            (Self::ImageFailed(n), En) => format!("Image {}: ", n),
+            (Self::ImageFailed(n), Zh) => format!("第 {} 张图片：", n),
+            (Self::ImageFailed(n), Ja) => format!("画像 {}：", n),
+            (Self::QueuePosition(n), En) => format!("You are #{} in line.", n),
+            (Self::QueuePosition(n), Zh) => format!("你排在第 {} 位。", n),
+            (Self::QueuePosition(n), Ja) => format!("待ち順は {} 番目です。", n),
+            (Self::Smile, En) => "Smile".into(),
+            (Self::Smile, Zh) => "笑".into(),
+            (Self::Smile, Ja) => "笑う".into(),
+            (Self::Cry, En) => "Cry".into(),
+            (Self::Cry, Zh) => "哭".into(),
+            (Self::Cry, Ja) => "泣く".into(),
+            (Self::Auto, En) => "Auto".into(),
+            (Self::Auto, Zh) => "自动".into(),
+            (Self::Auto, Ja) => "自動".into(),
+            (Self::Flip, En) => "Flip".into(),
+            (Self::Flip, Zh) => "翻转".into(),
+            (Self::Flip, Ja) => "反転".into(),
+        }
+    }
+}
+
+impl CiyaError {
+    pub fn localize(&self, lang: Language) -> String {
+        use Language::{En, Ja, Zh};
+        match (self, lang) {
+            (Self::ModelUnavailable, Zh) => "无法加载模型。".into(),
+            (Self::ModelUnavailable, Ja) => "モデルを読み込めませんでした。".into(),
+            (Self::InvalidImage, Zh) => "图片格式无效。".into(),
+            (Self::InvalidImage, Ja) => "画像の形式が無効です。".into(),
+            (Self::TooLarge, Zh) => "图片太大。".into(),
+            (Self::TooLarge, Ja) => "画像が大きすぎます。".into(),
+            (Self::AntialiasScale(n), Zh) => format!("antialias_scale 不能大于 {}。", n),
+            (Self::AntialiasScale(n), Ja) => {
+                format!("antialias_scale は {} 以下にしてください。", n)
+            }
+            (Self::RateLimited, Zh) => "请求太频繁，请稍后再试。".into(),
+            (Self::RateLimited, Ja) => "リクエストが多すぎます。少し待ってください。".into(),
+            (Self::Busy, Zh) => "机器人正忙，请稍后再试。".into(),
+            (Self::Busy, Ja) => "ボットが混み合っています。後でもう一度お試しください。".into(),
+            (Self::Internal, Zh) => "内部错误。".into(),
+            (Self::Internal, Ja) => "内部エラーです。".into(),
+            (Self::NotImplemented, Zh) => "尚未实现标准检测器。".into(),
+            (Self::NotImplemented, Ja) => "標準検出器はまだ実装されていません。".into(),
+            (Self::NoFace, Zh) => "未检测到脸或嘴。".into(),
+            (Self::NoFace, Ja) => "顔または口が検出されませんでした。".into(),
+            // English, and errors of lower layers which are only meaningful
+            // in their original wording
+            (_, En) | (Self::Ciya(_) | Self::Request(_), _) => self.to_string(),
+        }
+    }
+}
+
+// Help of the options to /ciyaify and /settings.
+pub fn help(lang: Language) -> String {
+    use Language::{En, Ja, Zh};
+    let command = Opt::command();
+    let (usage, arguments, options, args) = match lang {
+        En => return command.render_long_help().to_string(),
+        Zh => (
+            "用法:",
+            "参数",
+            "选项",
+            [
+                ("emotion", "默认为聊天设置，或 auto。"),
+                ("mode", "默认为聊天设置，或 weeb。"),
+                ("antialias_scale", "默认为聊天设置，或 8。"),
+                ("output", "默认为聊天设置，或 auto。"),
+                ("language", "默认为聊天设置，或你的 Telegram 客户端语言。"),
+            ],
+        ),
+        Ja => (
+            "使い方:",
+            "引数",
+            "オプション",
+            [
+                ("emotion", "省略時はチャットの設定、または auto。"),
+                ("mode", "省略時はチャットの設定、または weeb。"),
+                ("antialias_scale", "省略時はチャットの設定、または 8。"),
+                ("output", "省略時はチャットの設定、または auto。"),
+                (
+                    "language",
+                    "省略時はチャットの設定、または Telegram クライアントの言語。",
+                ),
+            ],
+        ),
+    };
+
+    let mut command = command
+        .disable_version_flag(true)
+        .help_template(format!("{} {{usage}}\n\n{{all-args}}", usage));
+    for (name, help) in args {
+        command = command.mut_arg(name, |arg| {
+            let heading = if arg.is_positional() {
+                arguments
+            } else {
+                options
+            };
+            arg.help(help).help_heading(heading)
+        });
+    }
+    command.render_long_help().to_string()
+}
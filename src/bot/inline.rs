@@ -0,0 +1,169 @@
+use std::{str::FromStr, sync::Arc};
+
+use futures::future;
+use teloxide::{
+    prelude::*,
+    types::{InlineQueryResult, InlineQueryResultCachedSticker},
+};
+use tracing::warn;
+
+use crate::{
+    commands::{CliEmotion, Opt},
+    i18n::{Language, Text},
+    media::{send_file, sent_file_id, MediaKind},
+    pipeline::{ciyaify_file, CiyaError, Origin},
+    settings::Settings,
+    state::{Source, State},
+    store::{ResultKey, Store},
+};
+
+// Uncached results rendered for a query, at most. Telegram drops answers that
+// take too long, so the rest is offered by later queries, once cached.
+const INLINE_RENDERS: usize = 2;
+
+// Ciyaify `source` and upload it to the cache chat, returning the file id of
+// the resulting sticker.
+async fn render_sticker(
+    bot: &Bot,
+    state: &Arc<State>,
+    store: &Store,
+    cache_chat: ChatId,
+    source: &Source,
+    settings: &Settings,
+) -> Result<String, CiyaError> {
+    let key = ResultKey::new(source, settings, MediaKind::Sticker);
+    // the query was charged as a whole already
+    let origin = Origin {
+        user: None,
+        chat: None,
+        language: Language::resolve(settings.language, None),
+    };
+    let file = ciyaify_file(
+        bot,
+        state,
+        store,
+        origin,
+        source,
+        settings,
+        MediaKind::Sticker,
+    )
+    .await?;
+    let message = send_file(bot, cache_chat, file, MediaKind::Sticker, None).await?;
+    store.cache_result(&key, &message);
+    sent_file_id(&message)
+        .map(String::from)
+        .ok_or(CiyaError::InvalidImage)
+}
+
+#[tracing::instrument(skip_all, fields(user = query.from.id.0, query = %query.query))]
+pub async fn answer_inline(
+    bot: Bot,
+    query: InlineQuery,
+    state: Arc<State>,
+    store: Arc<Store>,
+) -> ResponseResult<()> {
+    // inline queries have no chat, use settings of the private chat instead
+    let chat_settings = store.settings(query.from.id.into());
+    let lang = Language::resolve(chat_settings.language, Some(&query.from));
+    let user = query.from.id;
+    let cache_chat = match state.config.cache_chat() {
+        None => {
+            bot.answer_inline_query(query.id, [])
+                .switch_pm_text(Text::InlineUnavailable.localize(lang))
+                .switch_pm_parameter("inline")
+                .await?;
+            return Ok(());
+        }
+        Some(cache_chat) => cache_chat,
+    };
+
+    let opt = Opt::from_str(&query.query).unwrap_or_default();
+    let settings = chat_settings.merge(&opt);
+    let emotions = match opt.emotion {
+        None => vec![CliEmotion::Smile, CliEmotion::Cry],
+        Some(emotion) => vec![emotion],
+    };
+
+    let sources = state.recent(query.from.id);
+    if sources.is_empty() {
+        bot.answer_inline_query(query.id, [])
+            .switch_pm_text(Text::SendImageFirst.localize(lang))
+            .switch_pm_parameter("inline")
+            .cache_time(0)
+            .is_personal(true)
+            .await?;
+        return Ok(());
+    }
+
+    let jobs: Vec<_> = sources
+        .iter()
+        .flat_map(|source| {
+            emotions.iter().map(|emotion| {
+                let settings = Settings {
+                    emotion: *emotion,
+                    ..settings.clone()
+                };
+                let cached =
+                    store.cached_result(&ResultKey::new(source, &settings, MediaKind::Sticker));
+                (source, *emotion, settings, cached)
+            })
+        })
+        .collect();
+
+    // Telegram sends a query per keystroke, so only queries which render count
+    // against the rate limit, and only once
+    let uncached = jobs.iter().filter(|(.., cached)| cached.is_none());
+    let rate_limited = uncached.clone().next().is_some() && !state.check_rate(Some(user), None);
+    let renders: Vec<_> = if rate_limited {
+        vec![]
+    } else {
+        future::join_all(
+            uncached
+                .take(INLINE_RENDERS)
+                .map(|(source, _, settings, _)| {
+                    render_sticker(&bot, &state, &store, cache_chat, source, settings)
+                }),
+        )
+        .await
+    };
+
+    let mut renders = renders.into_iter();
+    let mut results = Vec::new();
+    for (source, emotion, _, cached) in &jobs {
+        let file_id = match cached {
+            Some(file_id) => file_id.clone(),
+            None => match renders.next() {
+                Some(Ok(file_id)) => file_id,
+                Some(Err(CiyaError::Request(e))) => return Err(e),
+                Some(Err(e)) => {
+                    warn!("Skipping inline result for {}: {}", source.unique_id, e);
+                    continue;
+                }
+                // left for a later query
+                None => continue,
+            },
+        };
+        results.push(InlineQueryResult::CachedSticker(
+            InlineQueryResultCachedSticker::new(
+                format!("{}:{:?}", source.unique_id, emotion),
+                file_id,
+            ),
+        ));
+    }
+
+    if results.is_empty() && rate_limited {
+        bot.answer_inline_query(query.id, [])
+            .switch_pm_text(CiyaError::RateLimited.localize(lang))
+            .switch_pm_parameter("inline")
+            .cache_time(0)
+            .is_personal(true)
+            .await?;
+        return Ok(());
+    }
+
+    bot.answer_inline_query(query.id, results)
+        .cache_time(0)
+        .is_personal(true)
+        .await?;
+    Ok(())
+}
@@ -1,223 +1,249 @@
 extern crate ciya_lib;
 
-use std::{collections::HashMap, io::Cursor};
+use std::{process, sync::Arc};
 
-use anyhow::{anyhow, Result};
-use ciya_lib::{ciyafier::Ciyafier, detectors::WeebDetector, errors::Error};
-use clap::CommandFactory;
-use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
-use log::{info, warn};
 use teloxide::{
-    net::Download,
     prelude::*,
-    types::{ChatAction, InputFile, Message, PhotoSize},
-    utils::command::BotCommands,
+    types::{ChatAction, Message},
 };
+use tracing::{info, warn};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
 use crate::{
-    commands::{Commands, Mode, Opt},
-    resources::ensure_models,
+    callback::{answer_callback, keyboard},
+    commands::{Commands, Opt},
+    config::{Config, LogFormat},
+    i18n::{Language, Text},
+    inline::answer_inline,
+    media::{image_from_message, send_file, send_files},
+    pipeline::{ciyaify_file, with_detector, CiyaError, Origin},
+    settings::Settings,
+    state::{Render, Source, State},
+    store::{ResultKey, Store},
 };
 
+mod callback;
 mod commands;
+mod config;
+mod i18n;
+mod inline;
+mod media;
+mod metrics;
+mod pipeline;
+mod queue;
+#[cfg(not(feature = "embed-models"))]
 mod resources;
+mod settings;
+mod state;
+mod store;
+mod webhook;
 
-fn best_photos(photos: &[PhotoSize]) -> Vec<&PhotoSize> {
-    let mut unique_photos: HashMap<&str, &PhotoSize> = HashMap::new();
-    for photo in photos {
-        let id = photo.file.unique_id.as_str();
-        if let Some(photo_in_map) = unique_photos.get(id) {
-            if photo.height > photo_in_map.height {
-                unique_photos.insert(id, photo);
-            }
-        } else {
-            unique_photos.insert(id, photo);
+// Settings of group chats may only be changed by administrators.
+async fn may_change_settings(bot: &Bot, msg: &Message) -> ResponseResult<bool> {
+    if msg.chat.is_private() {
+        return Ok(true);
+    }
+    match msg.from() {
+        None => Ok(false),
+        Some(user) => Ok(bot
+            .get_chat_member(msg.chat.id, user.id)
+            .await?
+            .is_privileged()),
+    }
+}
+
+async fn answer_settings(
+    bot: &Bot,
+    msg: &Message,
+    opt: Option<Opt>,
+    state: &State,
+    store: &Store,
+) -> ResponseResult<Message> {
+    let settings = store.settings(msg.chat.id);
+    let lang = Language::resolve(settings.language, msg.from());
+    if opt.as_ref().map_or(false, Opt::is_empty) {
+        return bot.send_message(msg.chat.id, settings.to_string()).await;
+    }
+    if !may_change_settings(bot, msg).await? {
+        return bot
+            .send_message(msg.chat.id, Text::AdminOnly.localize(lang))
+            .await;
+    }
+
+    let reset = opt.is_none();
+    let settings = opt.map_or_else(Settings::default, |opt| settings.merge(&opt));
+    if settings.antialias_scale > state.config.max_antialias_scale {
+        return bot
+            .send_message(
+                msg.chat.id,
+                CiyaError::AntialiasScale(state.config.max_antialias_scale).localize(lang),
+            )
+            .await;
+    }
+    // reply in the language just chosen
+    let lang = Language::resolve(settings.language, msg.from());
+    let saved = if reset {
+        store.reset_settings(msg.chat.id)
+    } else {
+        store.set_settings(msg.chat.id, &settings)
+    };
+    match saved {
+        Ok(()) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("{}\n{}", Text::SettingsUpdated.localize(lang), settings),
+            )
+            .await
+        }
+        Err(e) => {
+            warn!("Unable to save settings of chat {}: {}", msg.chat.id, e);
+            bot.send_message(msg.chat.id, Text::SettingsNotSaved.localize(lang))
+                .await
         }
     }
-    unique_photos.into_values().collect()
 }
 
-fn image_from_message(message: &Message) -> Option<&str> {
-    message
-        .document()
-        .and_then(|doc| {
-            doc.mime_type.as_ref().and_then(|mime| {
-                if mime.type_() == mime::IMAGE {
-                    Some(doc.file.id.as_str())
-                } else {
-                    None
-                }
-            })
-        })
-        .or_else(|| {
-            message
-                .photo()
-                .and_then(|photos| Some(best_photos(photos).first()?.file.id.as_str()))
-        })
-        .or_else(|| {
-            message
-                .sticker()
-                .and_then(|sticker| (!sticker.is_animated()).then_some(sticker.file.id.as_str()))
-        })
+// The images a command replies to: the whole album if the message is part of
+// one, otherwise the image of the message itself.
+fn sources_of(msg: &Message, state: &State) -> Vec<Source> {
+    if let Some(group) = msg.media_group_id() {
+        let album = state.album(msg.chat.id, group);
+        if !album.is_empty() {
+            return album;
+        }
+    }
+    image_from_message(msg)
+        .map(|(file, kind)| Source::new(file, kind))
+        .into_iter()
+        .collect()
 }
 
-fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
-    let guessed_image = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
-    if guessed_image.format() == Some(ImageFormat::WebP) {
-        let decoder = webp::Decoder::new(bytes);
-        Ok(decoder
-            .decode()
-            .ok_or_else(|| anyhow!("some decode thing failed"))?
-            .to_image())
-    } else {
-        Ok(guessed_image.decode()?)
+// Ciyaify `sources` and send the results. A single result comes with buttons to
+// re-render it, several are sent as albums followed by a report of failed ones.
+async fn send_ciyaified(
+    bot: &Bot,
+    msg: &Message,
+    sources: &[Source],
+    settings: &Settings,
+    state: &Arc<State>,
+    store: &Store,
+) -> ResponseResult<()> {
+    #[allow(unused_must_use)]
+    {
+        bot.send_chat_action(msg.chat.id, ChatAction::Typing).await;
+    };
+
+    let lang = Language::resolve(settings.language, msg.from());
+    let origin = Origin {
+        user: msg.from().map(|user| user.id),
+        chat: Some(msg.chat.id),
+        language: lang,
+    };
+    if let [source] = sources {
+        let kind = source.kind.resolve(settings.output);
+        match ciyaify_file(bot, state, store, origin, source, settings, kind).await {
+            Ok(file) => {
+                let sent = send_file(
+                    bot,
+                    msg.chat.id,
+                    file,
+                    kind,
+                    Some(keyboard(state.config.max_antialias_scale, lang)),
+                )
+                .await?;
+                store.cache_result(&ResultKey::new(source, settings, kind), &sent);
+                state.remember_render(
+                    sent.chat.id,
+                    sent.id,
+                    Render {
+                        source: source.clone(),
+                        settings: settings.clone(),
+                        requester: origin.user,
+                    },
+                );
+            }
+            Err(CiyaError::Request(err)) => return Err(err),
+            Err(err) => {
+                bot.send_message(msg.chat.id, err.localize(lang)).await?;
+            }
+        }
+        return Ok(());
     }
-    .and_then(|image| {
-        if image.width() > 4096 || image.height() > 4096 {
-            Err(anyhow!("Image too large"))
-        } else {
-            Ok(image)
+
+    let mut files = Vec::new();
+    let mut keys = Vec::new();
+    let mut failures = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let kind = source.kind.resolve(settings.output);
+        match ciyaify_file(bot, state, store, origin, source, settings, kind).await {
+            Ok(file) => {
+                files.push((file, kind));
+                keys.push(ResultKey::new(source, settings, kind));
+            }
+            Err(CiyaError::Request(err)) => return Err(err),
+            Err(err) => failures.push(format!(
+                "{}{}",
+                Text::ImageFailed(i + 1).localize(lang),
+                err.localize(lang)
+            )),
         }
-    })
+    }
+    let sent = send_files(bot, msg.chat.id, files).await?;
+    for (key, message) in keys.iter().zip(&sent) {
+        store.cache_result(key, message);
+    }
+    if !failures.is_empty() {
+        bot.send_message(msg.chat.id, failures.join("\n")).await?;
+    }
+    Ok(())
 }
 
-async fn answer(bot: Bot, msg: Message, command: Commands) -> ResponseResult<()> {
+#[tracing::instrument(skip_all, fields(chat = msg.chat.id.0, message = msg.id.0))]
+async fn answer(
+    bot: Bot,
+    msg: Message,
+    command: Commands,
+    state: Arc<State>,
+    store: Arc<Store>,
+) -> ResponseResult<()> {
     match command {
         Commands::Help | Commands::Start => {
-            bot.send_message(msg.chat.id, Opt::command().render_long_help().to_string())
-                .await?
+            let lang = Language::resolve(store.settings(msg.chat.id).language, msg.from());
+            bot.send_message(msg.chat.id, i18n::help(lang)).await?;
+        }
+        Commands::Settings(opt) => match opt {
+            Err(err) => {
+                bot.send_message(msg.chat.id, err.to_string()).await?;
+            }
+            Ok(opt) => {
+                answer_settings(&bot, &msg, Some(opt), &state, &store).await?;
+            }
+        },
+        Commands::ResetSettings => {
+            answer_settings(&bot, &msg, None, &state, &store).await?;
         }
         Commands::Ciyaify(opt) => match opt {
-            Err(err) => bot.send_message(msg.chat.id, err.to_string()).await?,
+            Err(err) => {
+                bot.send_message(msg.chat.id, err.to_string()).await?;
+            }
             Ok(opt) => {
-                let message = msg.reply_to_message();
-                match message {
-                    None => {
-                        bot.send_message(
-                            msg.chat.id,
-                            "Please reply to the image you want to ciyaify.",
-                        )
-                        .await?
-                    }
-                    Some(message) => {
-                        let file_id = image_from_message(message);
-                        match file_id {
-                            None => {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "Please reply to the image you want to ciyaify.",
-                                )
-                                .await?
-                            }
-                            Some(file_id) => {
-                                if opt.antialias_scale > 8 {
-                                    bot.send_message(msg.chat.id, "antialias_scale must <= 8.")
-                                        .await?
-                                } else {
-                                    #[allow(unused_must_use)]
-                                    {
-                                        bot.send_chat_action(msg.chat.id, ChatAction::Typing).await;
-                                    };
-
-                                    let mut buffer = Vec::new();
-                                    bot.download_file(
-                                        &bot.get_file(file_id).await?.path,
-                                        &mut buffer,
-                                    )
-                                    .await?;
-                                    info!("Downloading model");
-                                    let models = ensure_models();
-                                    info!("Model downloaded");
-                                    match models {
-                                        None => {
-                                            bot.send_message(msg.chat.id, "Unable to load model.")
-                                                .await?
-                                        }
-                                        Some((face_model, landmark_model)) => {
-                                            match decode_image(&buffer) {
-                                                Err(_) => {
-                                                    bot.send_message(
-                                                        msg.chat.id,
-                                                        "Invalid image format.",
-                                                    )
-                                                    .await?
-                                                }
-                                                Ok(image) => {
-                                                    let output = {
-                                                        let detector = match opt.mode {
-                                                            Mode::Weeb => Box::new(
-                                                                match WeebDetector::new(
-                                                                    face_model.to_str().unwrap(),
-                                                                    landmark_model
-                                                                        .to_str()
-                                                                        .unwrap(),
-                                                                ) {
-                                                                    Ok(detector) => detector,
-                                                                    Err(e) => {
-                                                                        warn!(
-                                                                            "Unable to load \
-                                                                             model: {}",
-                                                                            e
-                                                                        );
-                                                                        return Ok(());
-                                                                    }
-                                                                },
-                                                            ),
-                                                            Mode::Standard => {
-                                                                bot.send_message(
-                                                                    msg.chat.id,
-                                                                    "Standard detector not \
-                                                                     implemented.",
-                                                                )
-                                                                .await?;
-                                                                return Ok(());
-                                                            }
-                                                        };
-                                                        let ciyaify = Ciyafier::new(detector);
-                                                        ciyaify.ciya(
-                                                            image,
-                                                            opt.emotion.into(),
-                                                            opt.antialias_scale,
-                                                        )
-                                                    };
-                                                    match output {
-                                                        Err(Error::NoneError) => {
-                                                            bot.send_message(
-                                                                msg.chat.id,
-                                                                "No face or mouth detected.",
-                                                            )
-                                                            .await?
-                                                        }
-                                                        Err(err) => {
-                                                            bot.send_message(
-                                                                msg.chat.id,
-                                                                format!("{}", err),
-                                                            )
-                                                            .await?
-                                                        }
-                                                        Ok(output) => {
-                                                            let encoder =
-                                                                webp::Encoder::from_image(&output)
-                                                                    .unwrap();
-                                                            let bytes: Vec<u8> =
-                                                                (*encoder.encode(80.)).to_vec();
-                                                            bot.send_document(
-                                                                msg.chat.id,
-                                                                InputFile::memory(bytes)
-                                                                    .file_name("ciya.webp"),
-                                                            )
-                                                            .await?
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                let sources = msg
+                    .reply_to_message()
+                    .map(|reply| sources_of(reply, &state))
+                    .unwrap_or_default();
+                let settings = store.settings(msg.chat.id).merge(&opt);
+                if sources.is_empty() {
+                    let lang = Language::resolve(settings.language, msg.from());
+                    bot.send_message(msg.chat.id, Text::ReplyToImage.localize(lang))
+                        .await?;
+                    return Ok(());
+                }
+                if let Some(user) = msg.from() {
+                    for source in sources.iter().rev() {
+                        state.remember(user.id, source.clone());
                     }
                 }
+                send_ciyaified(&bot, &msg, &sources, &settings, &state, &store).await?;
             }
         },
     };
@@ -225,12 +251,155 @@ async fn answer(bot: Bot, msg: Message, command: Commands) -> ResponseResult<()>
     Ok(())
 }
 
+// Keep track of images users send, so that they can be picked in inline mode
+// and albums can be ciyaified as a whole.
+fn remember_source(msg: &Message, state: &State) -> Option<Source> {
+    let (file, kind) = image_from_message(msg)?;
+    let source = Source::new(file, kind);
+    if let Some(group) = msg.media_group_id() {
+        state.remember_album(msg.chat.id, group, msg.id, source.clone());
+    }
+    if let Some(user) = msg.from() {
+        state.remember(user.id, source.clone());
+    }
+    Some(source)
+}
+
+#[allow(clippy::unused_async)]
+async fn remember_image(msg: Message, state: Arc<State>) -> ResponseResult<()> {
+    remember_source(&msg, &state);
+    Ok(())
+}
+
+// Images sent in private chats are ciyaified right away, with the caption as
+// options.
+#[tracing::instrument(skip_all, fields(chat = msg.chat.id.0, message = msg.id.0))]
+async fn answer_image(
+    bot: Bot,
+    msg: Message,
+    state: Arc<State>,
+    store: Arc<Store>,
+) -> ResponseResult<()> {
+    let source = match remember_source(&msg, &state) {
+        Some(source) => source,
+        None => return Ok(()),
+    };
+    match Opt::from_caption(msg.caption().unwrap_or_default()) {
+        Err(err) => {
+            bot.send_message(msg.chat.id, err.to_string()).await?;
+        }
+        Ok(opt) => {
+            let settings = store.settings(msg.chat.id).merge(&opt);
+            send_ciyaified(&bot, &msg, &[source], &settings, &state, &store).await?;
+        }
+    }
+    Ok(())
+}
+
+fn init_logging(config: &Config) {
+    // spans are logged when they close, along with how long they took
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log_level))
+        .with_span_events(FmtSpan::CLOSE);
+    match config.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {:#}", e);
+        process::exit(1);
+    });
+    init_logging(&config);
     info!("Starting ciya_bot...");
 
-    let bot = Bot::from_env();
+    let bot = match config.api_url() {
+        Some(url) => Bot::from_env().set_api_url(url),
+        None => Bot::from_env(),
+    };
+    if config.cache_chat.is_none() {
+        info!("cache_chat not set, inline mode disabled");
+    }
+    let store = Arc::new(Store::open(&config.database).expect("Unable to open bot database"));
+    let state = Arc::new(State::new(config));
+
+    // load models ahead of the first request, so that /healthz reflects them
+    {
+        let state = Arc::clone(&state);
+        tokio::task::spawn_blocking(move || {
+            if with_detector(&state, |_| ()).is_ok() {
+                info!("Models loaded");
+            }
+        });
+    }
+    if let Some(address) = state.config.metrics_listen {
+        if let Err(e) = metrics::serve(address, Arc::clone(&state)) {
+            warn!("Unable to serve metrics: {:#}", e);
+        }
+    }
 
-    teloxide::commands_repl(bot, answer, Commands::ty()).await;
+    let handler = dptree::entry()
+        .branch(
+            Update::filter_message()
+                .filter(|msg: Message, state: Arc<State>| state.config.is_allowed(msg.chat.id))
+                .branch(
+                    dptree::entry()
+                        .filter_command::<Commands>()
+                        .endpoint(answer),
+                )
+                .branch(
+                    dptree::filter(|msg: Message| {
+                        msg.chat.is_private() && image_from_message(&msg).is_some()
+                    })
+                    .endpoint(answer_image),
+                )
+                .branch(dptree::endpoint(remember_image)),
+        )
+        .branch(
+            Update::filter_inline_query()
+                .filter(|query: InlineQuery, state: Arc<State>| {
+                    state.config.is_allowed(query.from.id.into())
+                })
+                .endpoint(answer_inline),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .filter(|query: CallbackQuery, state: Arc<State>| {
+                    query
+                        .message
+                        .map_or(false, |msg| state.config.is_allowed(msg.chat.id))
+                })
+                .endpoint(answer_callback),
+        );
+
+    let listener = if state.config.webhook_url.is_some() {
+        match webhook::listener(bot.clone(), &state.config).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                warn!("Unable to set up webhook, falling back to polling: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut dispatcher = Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![state, store])
+        .enable_ctrlc_handler()
+        .build();
+    match listener {
+        Some(listener) => {
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("An error from the webhook listener"),
+                )
+                .await;
+        }
+        None => dispatcher.dispatch().await,
+    }
 }
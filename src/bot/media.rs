@@ -0,0 +1,212 @@
+use std::io::{self, Cursor};
+
+use image::{imageops::FilterType, DynamicImage, ImageOutputFormat};
+use teloxide::{
+    prelude::*,
+    types::{
+        FileMeta,
+        InlineKeyboardMarkup,
+        InputFile,
+        InputMedia,
+        InputMediaDocument,
+        InputMediaPhoto,
+        Message,
+        PhotoSize,
+    },
+};
+
+use crate::commands::OutputType;
+
+// Telegram requires one side of a sticker to be exactly 512px and the other
+// one to be at most 512px.
+const STICKER_SIZE: u32 = 512;
+// Telegram allows at most 10 items in an album.
+const ALBUM_SIZE: usize = 10;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MediaKind {
+    Photo,
+    Document,
+    Sticker,
+}
+
+impl MediaKind {
+    pub const fn resolve(self, output: OutputType) -> Self {
+        match output {
+            OutputType::Auto => self,
+            OutputType::Photo => Self::Photo,
+            OutputType::Document => Self::Document,
+            OutputType::Sticker => Self::Sticker,
+        }
+    }
+}
+
+// A photo message carries several sizes of the same photo.
+fn largest_photo(photos: &[PhotoSize]) -> Option<&PhotoSize> {
+    photos.iter().max_by_key(|photo| photo.width * photo.height)
+}
+
+pub fn image_from_message(message: &Message) -> Option<(&FileMeta, MediaKind)> {
+    message
+        .document()
+        .and_then(|doc| {
+            doc.mime_type.as_ref().and_then(|mime| {
+                if mime.type_() == mime::IMAGE {
+                    Some((&doc.file, MediaKind::Document))
+                } else {
+                    None
+                }
+            })
+        })
+        .or_else(|| {
+            message
+                .photo()
+                .and_then(largest_photo)
+                .map(|photo| (&photo.file, MediaKind::Photo))
+        })
+        .or_else(|| {
+            message.sticker().and_then(|sticker| {
+                (!sticker.is_animated()).then_some((&sticker.file, MediaKind::Sticker))
+            })
+        })
+}
+
+fn encode_webp(image: &DynamicImage) -> io::Result<Vec<u8>> {
+    // the encoder only takes 8-bit RGB and RGBA, while grayscale or 16-bit
+    // sources stay as they are through the pipeline
+    let converted;
+    let image = match image {
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => image,
+        _ => {
+            converted = DynamicImage::ImageRgba8(image.to_rgba8());
+            &converted
+        }
+    };
+    let encoder = webp::Encoder::from_image(image)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    Ok((*encoder.encode(80.)).to_vec())
+}
+
+fn encode_png(image: &DynamicImage) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(bytes)
+}
+
+fn to_sticker(image: &DynamicImage) -> DynamicImage {
+    image.resize(STICKER_SIZE, STICKER_SIZE, FilterType::Lanczos3)
+}
+
+// Encode `image` for being sent as `kind`.
+pub fn input_file(image: &DynamicImage, kind: MediaKind) -> io::Result<InputFile> {
+    Ok(match kind {
+        MediaKind::Photo => InputFile::memory(encode_png(image)?).file_name("ciya.png"),
+        MediaKind::Document => InputFile::memory(encode_webp(image)?).file_name("ciya.webp"),
+        MediaKind::Sticker => {
+            InputFile::memory(encode_webp(&to_sticker(image))?).file_name("ciya.webp")
+        }
+    })
+}
+
+// The file id of the media sent in `message`, so that it can be sent again
+// without uploading.
+pub fn sent_file_id(message: &Message) -> Option<&str> {
+    message
+        .photo()
+        .and_then(largest_photo)
+        .map(|photo| photo.file.id.as_str())
+        .or_else(|| message.document().map(|doc| doc.file.id.as_str()))
+        .or_else(|| message.sticker().map(|sticker| sticker.file.id.as_str()))
+}
+
+pub async fn send_file(
+    bot: &Bot,
+    chat_id: ChatId,
+    file: InputFile,
+    kind: MediaKind,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> ResponseResult<Message> {
+    Ok(match kind {
+        MediaKind::Photo => {
+            let request = bot.send_photo(chat_id, file);
+            match keyboard {
+                Some(keyboard) => request.reply_markup(keyboard).await?,
+                None => request.await?,
+            }
+        }
+        MediaKind::Document => {
+            let request = bot.send_document(chat_id, file);
+            match keyboard {
+                Some(keyboard) => request.reply_markup(keyboard).await?,
+                None => request.await?,
+            }
+        }
+        MediaKind::Sticker => {
+            let request = bot.send_sticker(chat_id, file);
+            match keyboard {
+                Some(keyboard) => request.reply_markup(keyboard).await?,
+                None => request.await?,
+            }
+        }
+    })
+}
+
+fn input_media(file: InputFile, kind: MediaKind) -> InputMedia {
+    match kind {
+        MediaKind::Photo => InputMedia::Photo(InputMediaPhoto::new(file)),
+        MediaKind::Document => InputMedia::Document(InputMediaDocument::new(file)),
+        MediaKind::Sticker => unreachable!("stickers can't be sent as media"),
+    }
+}
+
+// Send several files, returning the sent messages in the same order. Runs of
+// photos or documents are grouped into albums, stickers are sent one by one.
+pub async fn send_files(
+    bot: &Bot,
+    chat_id: ChatId,
+    files: Vec<(InputFile, MediaKind)>,
+) -> ResponseResult<Vec<Message>> {
+    let mut sent = Vec::with_capacity(files.len());
+    let mut files = files.into_iter().peekable();
+    while let Some((file, kind)) = files.next() {
+        let mut album = vec![file];
+        if kind != MediaKind::Sticker {
+            while album.len() < ALBUM_SIZE {
+                match files.next_if(|(_, next)| *next == kind) {
+                    Some((file, _)) => album.push(file),
+                    None => break,
+                }
+            }
+        }
+
+        if album.len() == 1 {
+            let file = album.pop().unwrap();
+            sent.push(send_file(bot, chat_id, file, kind, None).await?);
+        } else {
+            let media = album.into_iter().map(|file| input_media(file, kind));
+            sent.extend(bot.send_media_group(chat_id, media).await?);
+        }
+    }
+    Ok(sent)
+}
+
+// Replace the media of `message` in place. Stickers can't be edited, so they
+// are deleted and sent again instead.
+pub async fn replace_file(
+    bot: &Bot,
+    message: &Message,
+    file: InputFile,
+    kind: MediaKind,
+    keyboard: InlineKeyboardMarkup,
+) -> ResponseResult<Message> {
+    if kind == MediaKind::Sticker {
+        let sent = send_file(bot, message.chat.id, file, kind, Some(keyboard)).await?;
+        bot.delete_message(message.chat.id, message.id).await?;
+        return Ok(sent);
+    }
+    bot.edit_message_media(message.chat.id, message.id, input_media(file, kind))
+        .reply_markup(keyboard)
+        .await
+}
@@ -0,0 +1,117 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::Extension,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use ciya_lib::errors::Error;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge,
+    register_histogram,
+    register_int_counter_vec,
+    register_int_gauge,
+    Encoder,
+    Gauge,
+    Histogram,
+    IntCounterVec,
+    IntGauge,
+    TextEncoder,
+};
+use tracing::{error, info};
+
+use crate::state::State;
+
+lazy_static! {
+    pub static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ciya_requests_total",
+        "Ciyaify requests by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref DETECTION_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "ciya_detection_failures_total",
+        "Failed mouth detections by reason.",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref INFERENCE_SECONDS: Histogram =
+        register_histogram!("ciya_inference_seconds", "Time spent detecting mouths.").unwrap();
+    pub static ref PROJECTION_SECONDS: Histogram = register_histogram!(
+        "ciya_projection_seconds",
+        "Time spent projecting ciya onto images."
+    )
+    .unwrap();
+    pub static ref MODEL_LOAD_SECONDS: Gauge = register_gauge!(
+        "ciya_model_load_seconds",
+        "Time spent loading models the last time they were loaded."
+    )
+    .unwrap();
+    static ref QUEUE_DEPTH: IntGauge =
+        register_int_gauge!("ciya_queue_depth", "Jobs waiting or running in the queue.").unwrap();
+}
+
+pub const fn detection_failure_reason(error: &Error) -> &'static str {
+    match error {
+        Error::NoneError => "no_face",
+        Error::CVError(_) => "opencv",
+        Error::OrtError(_) => "onnxruntime",
+        Error::ImageError(_) => "image",
+        Error::IOError(_) => "io",
+        Error::MathError(_) => "math",
+        Error::UnsupportedFormat => "unsupported_format",
+        Error::HttpError(_) => "http",
+        Error::ChecksumMismatch(_) => "checksum_mismatch",
+        Error::Offline(_) => "offline",
+    }
+}
+
+async fn metrics(Extension(state): Extension<Arc<State>>) -> impl IntoResponse {
+    QUEUE_DEPTH.set(i64::try_from(state.queue.depth()).unwrap_or(i64::MAX));
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, String::from("text/plain"))],
+            e.to_string().into_bytes(),
+        ),
+    }
+}
+
+async fn healthz(Extension(state): Extension<Arc<State>>) -> (StatusCode, &'static str) {
+    if state.models_loaded() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "models not loaded")
+    }
+}
+
+// Serve `/metrics` and `/healthz` on `address` in the background.
+pub fn serve(address: SocketAddr, state: Arc<State>) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .layer(Extension(state));
+    let server = axum::Server::try_bind(&address)
+        .with_context(|| format!("unable to listen on {}", address))?
+        .serve(app.into_make_service());
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server failed: {}", e);
+        }
+    });
+
+    info!("Serving metrics on {}", address);
+    Ok(())
+}
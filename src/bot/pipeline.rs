@@ -0,0 +1,280 @@
+use std::{sync::Arc, time::Instant};
+
+use ciya_lib::{
+    codec,
+    detectors::{MouthDetectorTrait, WeebDetector},
+    errors::Error,
+};
+use image::DynamicImage;
+use teloxide::{net::Download, prelude::*, types::InputFile, RequestError};
+use thiserror::Error;
+use tracing::warn;
+
+#[cfg(not(feature = "embed-models"))]
+use crate::resources::ensure_models;
+use crate::{
+    commands::Mode,
+    config::Config,
+    i18n::{Language, Text},
+    media::{input_file, MediaKind},
+    metrics::{
+        detection_failure_reason,
+        DETECTION_FAILURES,
+        INFERENCE_SECONDS,
+        MODEL_LOAD_SECONDS,
+        PROJECTION_SECONDS,
+        REQUESTS,
+    },
+    settings::Settings,
+    state::{Source, State},
+    store::{ResultKey, Store},
+};
+
+#[derive(Error, Debug)]
+pub enum CiyaError {
+    #[error("Unable to load model.")]
+    ModelUnavailable,
+    #[error("Invalid image format.")]
+    InvalidImage,
+    #[error("Image too large.")]
+    TooLarge,
+    #[error("antialias_scale must <= {0}.")]
+    AntialiasScale(u32),
+    #[error("You are sending too many requests, please slow down.")]
+    RateLimited,
+    #[error("The bot is busy, please try again later.")]
+    Busy,
+    #[error("Internal error.")]
+    Internal,
+    #[error("Standard detector not implemented.")]
+    NotImplemented,
+    #[error("No face or mouth detected.")]
+    NoFace,
+    #[error("{0}")]
+    Ciya(Error),
+    #[error(transparent)]
+    Request(#[from] RequestError),
+}
+
+impl CiyaError {
+    // Short label of the error for metrics.
+    pub const fn reason(&self) -> &'static str {
+        match self {
+            Self::ModelUnavailable => "model_unavailable",
+            Self::InvalidImage => "invalid_image",
+            Self::TooLarge => "too_large",
+            Self::AntialiasScale(_) => "antialias_scale",
+            Self::RateLimited => "rate_limited",
+            Self::Busy => "busy",
+            Self::Internal => "internal",
+            Self::NotImplemented => "not_implemented",
+            Self::NoFace => "no_face",
+            Self::Ciya(_) => "ciya",
+            Self::Request(_) => "request",
+        }
+    }
+}
+
+impl From<Error> for CiyaError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NoneError => Self::NoFace,
+            e => Self::Ciya(e),
+        }
+    }
+}
+
+fn decode_image(bytes: &[u8], max_dimension: u32) -> Result<DynamicImage, CiyaError> {
+    // checked before decoding, so that huge images are never allocated
+    let (width, height) = codec::dimensions(bytes).map_err(|_| CiyaError::InvalidImage)?;
+    if width > max_dimension || height > max_dimension {
+        return Err(CiyaError::TooLarge);
+    }
+    codec::decode(bytes).map_err(|_| CiyaError::InvalidImage)
+}
+
+#[tracing::instrument(skip(bot))]
+async fn download(bot: &Bot, file_id: &str, max_file_size: u32) -> Result<Vec<u8>, CiyaError> {
+    let file = bot.get_file(file_id).await?;
+    if file.size > max_file_size {
+        return Err(CiyaError::TooLarge);
+    }
+    let mut buffer = Vec::new();
+    bot.download_file(&file.path, &mut buffer)
+        .await
+        .map_err(RequestError::from)?;
+    Ok(buffer)
+}
+
+// Who a request comes from. Rate limits apply to both, and the queue position
+// is reported to the chat.
+#[derive(Debug, Copy, Clone)]
+pub struct Origin {
+    pub user: Option<UserId>,
+    pub chat: Option<ChatId>,
+    pub language: Language,
+}
+
+fn load_weeb_detector(config: &Config) -> Result<WeebDetector<'static>, CiyaError> {
+    let (face_model, landmark_model) = match (&config.face_model, &config.landmark_model) {
+        (Some(face_model), Some(landmark_model)) => (face_model.clone(), landmark_model.clone()),
+        #[cfg(feature = "embed-models")]
+        _ => {
+            return WeebDetector::embedded().map_err(|e| {
+                warn!("Unable to load embedded model: {}", e);
+                CiyaError::ModelUnavailable
+            })
+        }
+        #[cfg(not(feature = "embed-models"))]
+        _ => {
+            tracing::info!("Downloading model");
+            let models = ensure_models(config);
+            tracing::info!("Model downloaded");
+            models.ok_or(CiyaError::ModelUnavailable)?
+        }
+    };
+
+    WeebDetector::new(&face_model, &landmark_model).map_err(|e| {
+        warn!("Unable to load model: {}", e);
+        CiyaError::ModelUnavailable
+    })
+}
+
+// Load the models, keeping track of whether it succeeds and how long it takes.
+fn load_detector(state: &State) -> Result<WeebDetector<'static>, CiyaError> {
+    let start = Instant::now();
+    let detector = load_weeb_detector(&state.config);
+    if detector.is_ok() {
+        MODEL_LOAD_SECONDS.set(start.elapsed().as_secs_f64());
+    }
+    state.set_models_loaded(detector.is_ok());
+    detector
+}
+
+// Run `f` with the detector of `state`, loading it on first use. A failed load
+// is retried by the next caller.
+pub fn with_detector<T>(
+    state: &State,
+    f: impl FnOnce(&WeebDetector<'static>) -> T,
+) -> Result<T, CiyaError> {
+    let mut detector = state.detector.lock().unwrap_or_else(|e| {
+        // a panic during detection may have left the detector half way
+        state.detector.clear_poison();
+        let mut detector = e.into_inner();
+        *detector = None;
+        detector
+    });
+    if detector.is_none() {
+        *detector = Some(load_detector(state)?);
+    }
+    Ok(f(detector.as_ref().unwrap()))
+}
+
+fn ciyaify_blocking(
+    state: &State,
+    image: DynamicImage,
+    settings: &Settings,
+) -> Result<DynamicImage, CiyaError> {
+    let control_points = match settings.mode {
+        Mode::Weeb => with_detector(state, |detector| {
+            let _timer = INFERENCE_SECONDS.start_timer();
+            detector.detect(&image)
+        })?,
+        Mode::Standard => return Err(CiyaError::NotImplemented),
+    }
+    .map_err(|e| {
+        DETECTION_FAILURES
+            .with_label_values(&[detection_failure_reason(&e)])
+            .inc();
+        e
+    })?;
+
+    let _timer = PROJECTION_SECONDS.start_timer();
+    Ok(state.projector.project(
+        image,
+        control_points,
+        settings.emotion.into(),
+        settings.antialias_scale,
+    )?)
+}
+
+// Download the image behind `file_id` and ciyaify it according to `settings`.
+#[tracing::instrument(skip(bot, state, settings))]
+pub async fn ciyaify(
+    bot: &Bot,
+    state: &Arc<State>,
+    origin: Origin,
+    file_id: &str,
+    settings: &Settings,
+) -> Result<DynamicImage, CiyaError> {
+    let config = &state.config;
+    if settings.antialias_scale > config.max_antialias_scale {
+        return Err(CiyaError::AntialiasScale(config.max_antialias_scale));
+    }
+    if !state.check_rate(origin.user, origin.chat) {
+        return Err(CiyaError::RateLimited);
+    }
+
+    // queued before downloading, so that heavy images wait their turn too
+    let ticket = state.queue.enqueue().ok_or(CiyaError::Busy)?;
+    let notice = match origin.chat {
+        Some(chat) if ticket.position() > 0 => Some(
+            bot.send_message(
+                chat,
+                Text::QueuePosition(ticket.position()).localize(origin.language),
+            )
+            .await?,
+        ),
+        _ => None,
+    };
+
+    let output = async {
+        let buffer = download(bot, file_id, config.max_file_size).await?;
+        let max_dimension = config.max_dimension;
+        let state = Arc::clone(state);
+        let settings = settings.clone();
+        ticket
+            .run(move || {
+                let image = decode_image(&buffer, max_dimension)?;
+                ciyaify_blocking(&state, image, &settings)
+            })
+            .await
+            .map_err(|e| {
+                warn!("Ciyaify job failed: {}", e);
+                CiyaError::Internal
+            })?
+    }
+    .await;
+
+    if let Some(notice) = notice {
+        #[allow(unused_must_use)]
+        {
+            bot.delete_message(notice.chat.id, notice.id).await;
+        };
+    }
+    output
+}
+
+// Ciyaify `source` into a file to be sent as `kind`, reusing the previously
+// uploaded result if there's one.
+pub async fn ciyaify_file(
+    bot: &Bot,
+    state: &Arc<State>,
+    store: &Store,
+    origin: Origin,
+    source: &Source,
+    settings: &Settings,
+    kind: MediaKind,
+) -> Result<InputFile, CiyaError> {
+    if let Some(file_id) = store.cached_result(&ResultKey::new(source, settings, kind)) {
+        REQUESTS.with_label_values(&["cached"]).inc();
+        return Ok(InputFile::file_id(file_id));
+    }
+    let file = ciyaify(bot, state, origin, &source.file_id, settings)
+        .await
+        .and_then(|output| input_file(&output, kind).map_err(|e| RequestError::from(e).into()));
+    REQUESTS
+        .with_label_values(&[file.as_ref().map_or_else(CiyaError::reason, |_| "ok")])
+        .inc();
+    file
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::Semaphore, task::JoinError};
+use tracing::Span;
+
+// A bounded queue of jobs, of which at most `concurrency` run at the same time.
+pub struct JobQueue {
+    capacity: usize,
+    concurrency: usize,
+    // jobs either waiting or running
+    jobs: AtomicUsize,
+    permits: Semaphore,
+}
+
+pub struct Ticket<'a> {
+    queue: &'a JobQueue,
+    position: usize,
+}
+
+impl JobQueue {
+    pub fn new(concurrency: usize, capacity: usize) -> Self {
+        Self {
+            capacity,
+            concurrency,
+            jobs: AtomicUsize::new(0),
+            permits: Semaphore::new(concurrency),
+        }
+    }
+
+    // Reserve a place in the queue, or `None` if the queue is full.
+    pub fn enqueue(&self) -> Option<Ticket<'_>> {
+        let jobs = self.jobs.fetch_add(1, Ordering::SeqCst);
+        if jobs >= self.concurrency + self.capacity {
+            self.jobs.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Ticket {
+            queue: self,
+            position: (jobs + 1).saturating_sub(self.concurrency),
+        })
+    }
+
+    // How many jobs are either waiting or running.
+    pub fn depth(&self) -> usize {
+        self.jobs.load(Ordering::SeqCst)
+    }
+}
+
+impl Ticket<'_> {
+    // How many jobs are waiting in front of this one, including itself.
+    // Zero if the job may run immediately.
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+
+    // Wait for our turn, then run `job` on the blocking thread pool.
+    pub async fn run<T, F>(self, job: F) -> Result<T, JoinError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = self.queue.permits.acquire().await.unwrap();
+        // keep the job inside the span of the request it belongs to
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || span.in_scope(job)).await
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.queue.jobs.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Allows at most `limit` hits per key within a sliding window.
+pub struct RateLimiter<K> {
+    limit: usize,
+    window: Duration,
+    hits: Mutex<HashMap<K, VecDeque<Instant>>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    // A `limit` of zero disables rate limiting.
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Mutex::default(),
+        }
+    }
+
+    // Record a hit of `key`, returning false if it's over the limit.
+    pub fn check(&self, key: K) -> bool {
+        self.check_and(key, || true)
+    }
+
+    // Like `check`, but only if `then` returns true too. `then` is consulted
+    // only within the limit, and nothing is recorded if it refuses.
+    pub fn check_and(&self, key: K, then: impl FnOnce() -> bool) -> bool {
+        if self.limit == 0 {
+            return then();
+        }
+
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+        hits.retain(|_, times| {
+            while times
+                .front()
+                .map_or(false, |time| now.duration_since(*time) > self.window)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = hits.entry(key).or_default();
+        if times.len() >= self.limit || !then() {
+            false
+        } else {
+            times.push_back(now);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{JobQueue, RateLimiter};
+
+    #[test]
+    fn positions_follow_arrival() {
+        let queue = JobQueue::new(2, 3);
+        let tickets: Vec<_> = (0..5).map(|_| queue.enqueue().unwrap()).collect();
+        let positions: Vec<_> = tickets.iter().map(|ticket| ticket.position()).collect();
+        // the first two run immediately
+        assert_eq!(positions, [0, 0, 1, 2, 3]);
+        assert_eq!(queue.depth(), 5);
+    }
+
+    #[test]
+    fn rejects_over_capacity() {
+        let queue = JobQueue::new(1, 1);
+        let first = queue.enqueue().unwrap();
+        let _second = queue.enqueue().unwrap();
+        assert!(queue.enqueue().is_none());
+        // a rejected job doesn't take a place
+        assert_eq!(queue.depth(), 2);
+
+        drop(first);
+        assert_eq!(queue.enqueue().unwrap().position(), 1);
+    }
+
+    #[tokio::test]
+    async fn run_releases_place() {
+        let queue = JobQueue::new(1, 0);
+        let ticket = queue.enqueue().unwrap();
+        assert!(queue.enqueue().is_none());
+        assert_eq!(ticket.run(|| 42).await.unwrap(), 42);
+        assert_eq!(queue.depth(), 0);
+        assert!(queue.enqueue().is_some());
+    }
+
+    #[test]
+    fn limits_hits_per_key() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check(1));
+        assert!(limiter.check(1));
+        assert!(!limiter.check(1));
+        assert!(limiter.check(2));
+    }
+
+    #[test]
+    fn hits_expire_after_window() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert!(limiter.check(1));
+        assert!(!limiter.check(1));
+        thread::sleep(Duration::from_millis(60));
+        assert!(limiter.check(1));
+    }
+
+    #[test]
+    fn refused_hits_are_not_recorded() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        assert!(!limiter.check_and(1, || false));
+        assert!(limiter.check(1));
+        // over the limit, `then` isn't consulted
+        assert!(!limiter.check_and(1, || unreachable!()));
+    }
+
+    #[test]
+    fn zero_limit_disables() {
+        let limiter = RateLimiter::new(0, Duration::from_secs(60));
+        assert!((0..100).all(|_| limiter.check(1)));
+        assert!(!limiter.check_and(1, || false));
+    }
+}
@@ -1,69 +1,43 @@
-use std::{
-    fs::File,
-    io::Write,
-    path::{Path, PathBuf},
-    sync::Once,
-};
+use std::{path::PathBuf, sync::Mutex};
 
-use anyhow::{anyhow, Result};
-use reqwest::blocking::Client;
+use ciya_lib::{errors::Result, models::ModelManager};
+use lazy_static::lazy_static;
+use tracing::{debug, warn};
 
-const FACE_MODEL_URL: &str = "https://raw.githubusercontent.com/nagadomi/lbpcascade_animeface/master/lbpcascade_animeface.xml";
-const LANDMARK_MODEL_URL: &str =
-    "https://github.com/PhotonQuantum/ciya-rs/releases/download/v0.0.1/anime_face_landmark.onnx";
+use crate::config::Config;
 
-static mut MODELS: Option<(PathBuf, PathBuf)> = None;
-static INIT: Once = Once::new();
-
-pub fn ensure_models() -> &'static Option<(PathBuf, PathBuf)> {
-    unsafe {
-        INIT.call_once(|| {
-            MODELS = _ensure_models().ok();
-        });
-        &MODELS
-    }
+lazy_static! {
+    // Only successes are kept, so that a failure is retried next time.
+    static ref MODELS: Mutex<Option<(PathBuf, PathBuf)>> = Mutex::default();
 }
 
-fn _ensure_models() -> Result<(PathBuf, PathBuf)> {
-    let current_dir = std::env::current_dir()?;
-    let data_dir = dirs::data_local_dir().ok_or_else(|| anyhow!("Missing data dir"))?;
-    let local_path = (
-        current_dir.join("lbpcascade_animeface.xml"),
-        current_dir.join("anime_face_landmark.onnx"),
-    );
-    let download_path = (
-        data_dir.join("ciya-rs").join("lbpcascade_animeface.xml"),
-        data_dir.join("ciya-rs").join("anime_face_landmark.onnx"),
-    );
-
-    if local_path.0.is_file() && local_path.1.is_file() {
-        Ok(local_path)
-    } else if download_path.0.is_file() && download_path.1.is_file() {
-        Ok(download_path)
-    } else {
-        let http = Client::new();
-        if !(download_path.0.is_file()) {
-            let face_model = http.get(FACE_MODEL_URL).send()?;
-            ensure_dir(download_path.0.parent().unwrap())?;
-            let mut file = File::create(&download_path.0)?;
-            file.write_all(&face_model.bytes()?)?;
-        }
-        if !(download_path.1.is_file()) {
-            let landmark_model = http.get(LANDMARK_MODEL_URL).send()?;
-            ensure_dir(download_path.1.parent().unwrap())?;
-            let mut file = File::create(&download_path.1)?;
-            file.write_all(&landmark_model.bytes()?)?;
-        }
-        Ok(download_path)
+pub fn ensure_models(config: &Config) -> Option<(PathBuf, PathBuf)> {
+    let mut models = MODELS.lock().unwrap();
+    if models.is_none() {
+        *models = _ensure_models(config)
+            .map_err(|e| warn!("Unable to prepare models: {}", e))
+            .ok();
     }
+    models.clone()
 }
 
-fn ensure_dir(path: &Path) -> Result<()> {
-    if path.is_file() {
-        Err(anyhow!("Is a file"))
-    } else if path.exists() {
-        Ok(())
-    } else {
-        Ok(std::fs::create_dir_all(path)?)
+fn _ensure_models(config: &Config) -> Result<(PathBuf, PathBuf)> {
+    let mut manager = match &config.model_dir {
+        Some(model_dir) => ModelManager::new(model_dir),
+        None => ModelManager::with_default_root()?,
+    }
+    .offline(config.offline)
+    .mirrors(config.model_mirrors.clone())
+    .on_progress(|model, progress| {
+        debug!(
+            model = model.name,
+            downloaded = progress.downloaded,
+            total = progress.total,
+            "Downloading model"
+        );
+    });
+    if let Some(proxy) = &config.model_proxy {
+        manager = manager.proxy(proxy);
     }
+    manager.ensure_weeb()
 }
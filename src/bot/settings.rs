@@ -0,0 +1,69 @@
+use std::fmt::{self, Display, Formatter};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::{CliEmotion, Mode, Opt, OutputType},
+    i18n::Language,
+};
+
+// Fully resolved options of a ciyaify request, also used as per-chat defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub emotion: CliEmotion,
+    pub mode: Mode,
+    pub antialias_scale: u32,
+    pub output: OutputType,
+    // `None` follows the language of each user's client.
+    #[serde(default)]
+    pub language: Option<Language>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            emotion: CliEmotion::Auto,
+            mode: Mode::Weeb,
+            antialias_scale: 8,
+            output: OutputType::Auto,
+            language: None,
+        }
+    }
+}
+
+impl Settings {
+    // Override these settings with options given explicitly.
+    pub fn merge(&self, opt: &Opt) -> Self {
+        Self {
+            emotion: opt.emotion.unwrap_or(self.emotion),
+            mode: opt.mode.unwrap_or(self.mode),
+            antialias_scale: opt.antialias_scale.unwrap_or(self.antialias_scale),
+            output: opt.output.unwrap_or(self.output),
+            language: opt.language.or(self.language),
+        }
+    }
+}
+
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "emotion: {}", value_name(&self.emotion))?;
+        writeln!(f, "mode: {}", value_name(&self.mode))?;
+        writeln!(f, "antialias_scale: {}", self.antialias_scale)?;
+        writeln!(f, "output: {}", value_name(&self.output))?;
+        write!(
+            f,
+            "language: {}",
+            self.language
+                .as_ref()
+                .map_or_else(|| String::from("auto"), value_name)
+        )
+    }
+}
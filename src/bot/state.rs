@@ -0,0 +1,185 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use ciya_lib::{detectors::WeebDetector, projector::Projector};
+use teloxide::types::{ChatId, FileMeta, MessageId, UserId};
+
+use crate::{
+    config::Config,
+    media::MediaKind,
+    queue::{JobQueue, RateLimiter},
+    settings::Settings,
+};
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+// How many images per user are offered in inline mode.
+const RECENT_LIMIT: usize = 5;
+// How many results can be re-rendered through their inline keyboard.
+const RENDER_LIMIT: usize = 1024;
+// How many albums are remembered so that they can be ciyaified as a whole.
+const ALBUM_LIMIT: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub file_id: String,
+    pub unique_id: String,
+    pub kind: MediaKind,
+}
+
+impl Source {
+    pub fn new(file: &FileMeta, kind: MediaKind) -> Self {
+        Self {
+            file_id: file.id.clone(),
+            unique_id: file.unique_id.clone(),
+            kind,
+        }
+    }
+}
+
+// What a result message was rendered from.
+#[derive(Debug, Clone)]
+pub struct Render {
+    pub source: Source,
+    pub settings: Settings,
+    // only they may re-render it
+    pub requester: Option<UserId>,
+}
+
+#[derive(Default)]
+struct Renders {
+    renders: HashMap<(ChatId, MessageId), Render>,
+    order: VecDeque<(ChatId, MessageId)>,
+}
+
+#[derive(Default)]
+struct Albums {
+    albums: HashMap<(ChatId, String), Vec<(MessageId, Source)>>,
+    order: VecDeque<(ChatId, String)>,
+}
+
+pub struct State {
+    pub config: Config,
+    pub queue: JobQueue,
+    // loaded on first use, see `pipeline::with_detector`
+    pub detector: Mutex<Option<WeebDetector<'static>>>,
+    pub projector: Projector,
+    user_limiter: RateLimiter<UserId>,
+    chat_limiter: RateLimiter<ChatId>,
+    recent: Mutex<HashMap<UserId, VecDeque<Source>>>,
+    renders: Mutex<Renders>,
+    albums: Mutex<Albums>,
+    models_loaded: AtomicBool,
+}
+
+impl State {
+    pub fn new(config: Config) -> Self {
+        Self {
+            queue: JobQueue::new(config.concurrency, config.queue_size),
+            user_limiter: RateLimiter::new(config.user_rate_limit, RATE_LIMIT_WINDOW),
+            chat_limiter: RateLimiter::new(config.chat_rate_limit, RATE_LIMIT_WINDOW),
+            detector: Mutex::default(),
+            projector: Projector::new(),
+            config,
+            recent: Mutex::default(),
+            renders: Mutex::default(),
+            albums: Mutex::default(),
+            models_loaded: AtomicBool::new(false),
+        }
+    }
+
+    // Whether models were loaded successfully the last time they were needed.
+    pub fn models_loaded(&self) -> bool {
+        self.models_loaded.load(Ordering::SeqCst)
+    }
+
+    pub fn set_models_loaded(&self, loaded: bool) {
+        self.models_loaded.store(loaded, Ordering::SeqCst);
+    }
+
+    // Record a request, returning false if the user or the chat is sending too
+    // many of them. A rejected request counts against neither.
+    pub fn check_rate(&self, user: Option<UserId>, chat: Option<ChatId>) -> bool {
+        let check_chat = || chat.map_or(true, |chat| self.chat_limiter.check(chat));
+        match user {
+            Some(user) => self.user_limiter.check_and(user, check_chat),
+            None => check_chat(),
+        }
+    }
+
+    pub fn remember(&self, user: UserId, source: Source) {
+        let mut recent = self.recent.lock().unwrap();
+        let sources = recent.entry(user).or_default();
+        sources.retain(|s| s.unique_id != source.unique_id);
+        sources.push_front(source);
+        sources.truncate(RECENT_LIMIT);
+    }
+
+    pub fn recent(&self, user: UserId) -> Vec<Source> {
+        self.recent
+            .lock()
+            .unwrap()
+            .get(&user)
+            .map(|sources| sources.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn remember_album(&self, chat: ChatId, group: &str, message: MessageId, source: Source) {
+        let mut albums = self.albums.lock().unwrap();
+        let key = (chat, group.to_string());
+        if !albums.albums.contains_key(&key) {
+            albums.order.push_back(key.clone());
+        }
+        let items = albums.albums.entry(key).or_default();
+        // items of an album may arrive out of order
+        let index = items.partition_point(|(id, _)| id.0 < message.0);
+        items.insert(index, (message, source));
+        while albums.order.len() > ALBUM_LIMIT {
+            let oldest = albums.order.pop_front().unwrap();
+            albums.albums.remove(&oldest);
+        }
+    }
+
+    // Images of an album, in the order they were sent.
+    pub fn album(&self, chat: ChatId, group: &str) -> Vec<Source> {
+        self.albums
+            .lock()
+            .unwrap()
+            .albums
+            .get(&(chat, group.to_string()))
+            .map(|items| items.iter().map(|(_, source)| source.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn remember_render(&self, chat: ChatId, message: MessageId, render: Render) {
+        let mut renders = self.renders.lock().unwrap();
+        if renders.renders.insert((chat, message), render).is_none() {
+            renders.order.push_back((chat, message));
+        }
+        while renders.order.len() > RENDER_LIMIT {
+            let oldest = renders.order.pop_front().unwrap();
+            renders.renders.remove(&oldest);
+        }
+    }
+
+    pub fn render(&self, chat: ChatId, message: MessageId) -> Option<Render> {
+        self.renders
+            .lock()
+            .unwrap()
+            .renders
+            .get(&(chat, message))
+            .cloned()
+    }
+
+    pub fn forget_render(&self, chat: ChatId, message: MessageId) {
+        let mut renders = self.renders.lock().unwrap();
+        renders.renders.remove(&(chat, message));
+        renders.order.retain(|key| *key != (chat, message));
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use teloxide::types::{ChatId, Message};
+use tracing::warn;
+
+use crate::{
+    media::{sent_file_id, MediaKind},
+    settings::Settings,
+    state::Source,
+};
+
+// Identifies a ciyaified result. Results are only reused by the same version of
+// the bot, as the output may change between versions.
+pub struct ResultKey(String);
+
+impl ResultKey {
+    pub fn new(source: &Source, settings: &Settings, kind: MediaKind) -> Self {
+        Self(format!(
+            "{}/{}/{:?}/{:?}/{}/{:?}",
+            env!("CARGO_PKG_VERSION"),
+            source.unique_id,
+            settings.emotion,
+            settings.mode,
+            settings.antialias_scale,
+            kind
+        ))
+    }
+}
+
+// Persistent bot data backed by an embedded sled database.
+pub struct Store {
+    settings: sled::Tree,
+    // file ids of uploaded results
+    results: sled::Tree,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> sled::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            settings: db.open_tree("settings")?,
+            results: db.open_tree("results")?,
+        })
+    }
+
+    pub fn settings(&self, chat: ChatId) -> Settings {
+        match self.settings.get(chat.0.to_be_bytes()) {
+            Ok(Some(value)) => serde_json::from_slice(&value).unwrap_or_else(|e| {
+                warn!("Corrupted settings of chat {}: {}", chat, e);
+                Settings::default()
+            }),
+            Ok(None) => Settings::default(),
+            Err(e) => {
+                warn!("Unable to read settings of chat {}: {}", chat, e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn set_settings(&self, chat: ChatId, settings: &Settings) -> sled::Result<()> {
+        let value = serde_json::to_vec(settings).expect("settings are serializable");
+        self.settings.insert(chat.0.to_be_bytes(), value)?;
+        self.settings.flush()?;
+        Ok(())
+    }
+
+    pub fn reset_settings(&self, chat: ChatId) -> sled::Result<()> {
+        self.settings.remove(chat.0.to_be_bytes())?;
+        self.settings.flush()?;
+        Ok(())
+    }
+
+    pub fn cached_result(&self, key: &ResultKey) -> Option<String> {
+        match self.results.get(&key.0) {
+            Ok(value) => value.and_then(|value| String::from_utf8(value.to_vec()).ok()),
+            Err(e) => {
+                warn!("Unable to read cached result {}: {}", key.0, e);
+                None
+            }
+        }
+    }
+
+    // Remember the file id of a result sent in `message`.
+    pub fn cache_result(&self, key: &ResultKey, message: &Message) {
+        if let Some(file_id) = sent_file_id(message) {
+            if let Err(e) = self.results.insert(&key.0, file_id) {
+                warn!("Unable to cache result {}: {}", key.0, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::{convert::Infallible, net::TcpListener};
+
+use anyhow::{Context, Result};
+use teloxide::{
+    prelude::*,
+    update_listeners::{webhooks, UpdateListener},
+};
+use tracing::{error, info};
+
+use crate::config::Config;
+
+// Bind the webhook server and register it with Telegram.
+//
+// The port is bound before registering, so that a failure leaves no webhook
+// behind and the caller may fall back to long polling.
+pub async fn listener(bot: Bot, config: &Config) -> Result<impl UpdateListener<Infallible>> {
+    let url = config.webhook_url().context("webhook_url is not set")?;
+    let socket = TcpListener::bind(config.listen)
+        .with_context(|| format!("unable to listen on {}", config.listen))?;
+
+    let mut options = webhooks::Options::new(config.listen, url);
+    if let Some(path) = &config.webhook_path {
+        options = options.path(path.clone());
+    }
+    if let Some(secret) = &config.webhook_secret {
+        options = options.secret_token(secret.clone());
+    }
+
+    let (listener, stop_flag, router) = webhooks::axum_to_router(bot, options)
+        .await
+        .context("unable to set webhook")?;
+    let server = axum::Server::from_tcp(socket)
+        .context("unable to start webhook server")?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(stop_flag);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Webhook server failed: {}", e);
+        }
+    });
+
+    info!("Listening for webhook updates on {}", config.listen);
+    Ok(listener)
+}
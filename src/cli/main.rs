@@ -1,16 +1,17 @@
 extern crate ciya_lib;
 
-use std::path::PathBuf;
+use std::{fs, io, path::PathBuf};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use ciya_lib::{
-    ciyafier::{Ciyafier, Emotion},
+    ciyafier::{Blending, Ciyafier, Emotion},
+    codec,
     detectors::WeebDetector,
+    errors::Error,
+    models::{Model, ModelManager, Progress, MANIFEST},
 };
-use clap::{Parser, ValueEnum};
-use image::io::Reader as ImageReader;
-
-mod resources;
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Debug, Copy, Clone, ValueEnum)]
 enum Mode {
@@ -23,6 +24,7 @@ enum CliEmotion {
     Auto,
     Smile,
     Cry,
+    Flip,
 }
 
 impl From<CliEmotion> for Emotion {
@@ -31,50 +33,199 @@ impl From<CliEmotion> for Emotion {
             CliEmotion::Auto => Self::Auto,
             CliEmotion::Smile => Self::Smile,
             CliEmotion::Cry => Self::Cry,
+            CliEmotion::Flip => Self::Flip,
         }
     }
 }
 
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum CliBlending {
+    Linear,
+    Srgb,
+}
+
+impl From<CliBlending> for Blending {
+    fn from(v: CliBlending) -> Self {
+        match v {
+            CliBlending::Linear => Self::Linear,
+            CliBlending::Srgb => Self::Srgb,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Manage downloaded models.
+    #[command(subcommand)]
+    Models(ModelsCommand),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ModelsCommand {
+    /// List known models and whether they are downloaded.
+    List,
+    /// Download models that are missing or corrupt.
+    Fetch {
+        /// Download all models again.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check downloaded models against their hashes.
+    Verify,
+    /// Print the directory models are stored in.
+    Path,
+}
+
 #[derive(Debug, Clone, Parser)]
 #[command(name = "ciya-cli")]
 #[command(author, version, about)]
+#[command(subcommand_negates_reqs = true)]
 struct Opt {
-    input: PathBuf,
-    output: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required = true)]
+    input: Option<PathBuf>,
+    #[arg(required = true)]
+    output: Option<PathBuf>,
+    /// Directory to store models in. Defaults to $CIYA_MODEL_DIR, or the local
+    /// data directory.
+    #[arg(long, global = true)]
+    model_dir: Option<PathBuf>,
+    /// Fail instead of downloading missing models.
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Base URL to download models from before trying upstream. May be given
+    /// more than once.
+    #[arg(long = "mirror", global = true)]
+    mirrors: Vec<String>,
+    /// Proxy to download models through. Defaults to $HTTPS_PROXY.
+    #[arg(long, global = true)]
+    proxy: Option<String>,
     #[arg(short, long, value_enum, default_value_t = Mode::Weeb)]
     mode: Mode,
     #[arg(short, long, value_enum, default_value_t = CliEmotion::Auto)]
     emotion: CliEmotion,
+    /// Samples taken per axis of each pixel of the ciya. Higher is smoother
+    /// but slower.
     #[arg(short, long, default_value_t = 8)]
     antialias_scale: u32,
+    /// Color space the ciya is blended in. srgb darkens its edges, and is only
+    /// kept for comparison.
+    #[arg(long, value_enum, default_value_t = CliBlending::Linear)]
+    blending: CliBlending,
+}
+
+fn print_progress(model: &Model, progress: Progress) {
+    match progress.total {
+        Some(total) => eprint!(
+            "\rDownloading {}: {}/{} KiB",
+            model.name,
+            progress.downloaded / 1024,
+            total / 1024
+        ),
+        None => eprint!(
+            "\rDownloading {}: {} KiB",
+            model.name,
+            progress.downloaded / 1024
+        ),
+    }
+    if progress.total == Some(progress.downloaded) {
+        eprintln!();
+    }
+}
+
+fn models(manager: &ModelManager, command: ModelsCommand) -> Result<()> {
+    match command {
+        ModelsCommand::List => {
+            for model in MANIFEST {
+                let status = match manager.verify(model) {
+                    Ok(()) => String::from("ok"),
+                    Err(Error::IOError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                        String::from("missing")
+                    }
+                    Err(e) => e.to_string(),
+                };
+                println!(
+                    "{} {}\t{}\t{}",
+                    model.name,
+                    model.version,
+                    status,
+                    manager.path(model).display()
+                );
+            }
+        }
+        ModelsCommand::Fetch { force } => {
+            for model in MANIFEST {
+                let path = if force {
+                    manager.fetch(model)?
+                } else {
+                    manager.ensure(model)?
+                };
+                println!("{}", path.display());
+            }
+        }
+        ModelsCommand::Verify => {
+            let mut failed = false;
+            for model in MANIFEST {
+                if let Err(e) = manager.verify(model) {
+                    eprintln!("{} {}: {}", model.name, model.version, e);
+                    failed = true;
+                }
+            }
+            if failed {
+                bail!("some models failed verification");
+            }
+        }
+        ModelsCommand::Path => println!("{}", manager.root().display()),
+    }
+    Ok(())
+}
+
+fn init_logging() {
+    // warnings by default, like before the library logged through tracing
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .init();
 }
 
 fn main() -> Result<()> {
     let opt: Opt = Opt::parse();
+    init_logging();
+    let mut manager = match &opt.model_dir {
+        Some(model_dir) => ModelManager::new(model_dir),
+        None => ModelManager::with_default_root()?,
+    }
+    .offline(opt.offline)
+    .mirrors(opt.mirrors.clone())
+    .on_progress(print_progress);
+    if let Some(proxy) = &opt.proxy {
+        manager = manager.proxy(proxy);
+    }
+    if let Some(Command::Models(command)) = opt.command {
+        return models(&manager, command);
+    }
+
     let detector = match opt.mode {
+        #[cfg(feature = "embed-models")]
+        Mode::Weeb if opt.model_dir.is_none() => Box::new(WeebDetector::embedded()?),
         Mode::Weeb => {
-            let (face_model, landmark_model) = resources::ensure_models()?;
-            Box::new(WeebDetector::new(
-                face_model
-                    .to_str()
-                    .ok_or_else(|| anyhow!("some path thing error"))?,
-                landmark_model
-                    .to_str()
-                    .ok_or_else(|| anyhow!("some path thing error"))?,
-            )?)
+            let (face_model, landmark_model) = manager.ensure_weeb()?;
+            Box::new(WeebDetector::new(face_model, landmark_model)?)
         }
         Mode::Standard => {
             bail!("Standard mode not implemented")
         }
     };
     println!("Initializing");
-    let ciyafier = Ciyafier::new(detector);
+    let ciyafier = Ciyafier::new(detector).with_blending(opt.blending.into());
     println!("Reading file");
-    let image = ImageReader::open(opt.input).unwrap().decode().unwrap();
+    let image = codec::decode(&fs::read(opt.input.unwrap())?)?;
     println!("Processing image");
     let image = ciyafier.ciya(image, opt.emotion.into(), opt.antialias_scale)?;
     println!("Writing image");
-    image.save(opt.output)?;
+    image.save(opt.output.unwrap())?;
 
     Ok(())
 }
@@ -1,7 +1,10 @@
 use image::DynamicImage;
 
-pub use crate::projector::Emotion;
 use crate::{detectors::MouthDetectorTrait, errors::Result, projector::Projector};
+pub use crate::{
+    projector::{Blending, Emotion},
+    types::{ControlPoints, Point},
+};
 
 pub struct Ciyafier {
     detector: Box<dyn MouthDetectorTrait>,
@@ -17,13 +20,40 @@ impl Ciyafier {
         }
     }
 
+    /// Interpolate and composite the ciya with `blending`.
+    #[must_use]
+    pub fn with_blending(mut self, blending: Blending) -> Self {
+        self.projector = self.projector.with_blending(blending);
+        self
+    }
+
+    #[tracing::instrument(
+        skip(self, image),
+        fields(width = image.width(), height = image.height())
+    )]
     pub fn ciya(
         &self,
         image: DynamicImage,
         emotion: Emotion,
         antialias_scale: u32,
     ) -> Result<DynamicImage> {
-        let control_points = self.detector.detect(&image)?;
+        let control_points = self.detect(&image)?;
+        self.project(image, control_points, emotion, antialias_scale)
+    }
+
+    /// Locate the mouth, the first half of `ciya`.
+    pub fn detect(&self, image: &DynamicImage) -> Result<ControlPoints<f32>> {
+        self.detector.detect(image)
+    }
+
+    /// Warp the mouth at `control_points`, the second half of `ciya`.
+    pub fn project(
+        &self,
+        image: DynamicImage,
+        control_points: ControlPoints<f32>,
+        emotion: Emotion,
+        antialias_scale: u32,
+    ) -> Result<DynamicImage> {
         self.projector
             .project(image, control_points, emotion, antialias_scale)
     }
@@ -0,0 +1,38 @@
+use std::io::Cursor;
+
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
+use tracing::Span;
+
+use crate::errors::{Error, Result};
+
+/// Decode an image, guessing its format from the content.
+///
+/// WebP goes through libwebp, since `image` can't decode lossy WebP with alpha.
+#[tracing::instrument(level = "debug", skip_all, fields(bytes = bytes.len(), width, height))]
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let image = if reader.format() == Some(ImageFormat::WebP) {
+        webp::Decoder::new(bytes)
+            .decode()
+            .ok_or(Error::UnsupportedFormat)?
+            .to_image()
+    } else {
+        reader.decode()?
+    };
+
+    let span = Span::current();
+    span.record("width", image.width());
+    span.record("height", image.height());
+    Ok(image)
+}
+
+/// Width and height of an image, read from its header without decoding it.
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32)> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    if reader.format() == Some(ImageFormat::WebP) {
+        let features = webp::BitstreamFeatures::new(bytes).ok_or(Error::UnsupportedFormat)?;
+        Ok((features.width(), features.height()))
+    } else {
+        Ok(reader.into_dimensions()?)
+    }
+}
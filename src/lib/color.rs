@@ -0,0 +1,72 @@
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref SRGB_TO_LINEAR: [f32; 256] = {
+        let mut table = [0.; 256];
+        for (i, linear) in table.iter_mut().enumerate() {
+            *linear = decode(i as f32 / 255.);
+        }
+        table
+    };
+}
+
+fn decode(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn encode(c: f32) -> f32 {
+    let c = c.clamp(0., 1.);
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Linear light of an sRGB channel, in `0..=1`.
+pub fn srgb_to_linear(c: u8) -> f32 {
+    SRGB_TO_LINEAR[c as usize]
+}
+
+/// sRGB channel of linear light in `0..=1`.
+pub fn linear_to_srgb(c: f32) -> u8 {
+    (encode(c) * 255.).round() as u8
+}
+
+/// Linear light of a 16-bit sRGB channel, in `0..=1`.
+pub fn srgb16_to_linear(c: u16) -> f32 {
+    decode(f32::from(c) / 65535.)
+}
+
+/// 16-bit sRGB channel of linear light in `0..=1`.
+pub fn linear_to_srgb16(c: f32) -> u16 {
+    (encode(c) * 65535.).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_to_srgb, linear_to_srgb16, srgb16_to_linear, srgb_to_linear};
+
+    #[test]
+    fn round_trips() {
+        for c in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(c)), c);
+        }
+        for c in (0..=u16::MAX).step_by(7) {
+            assert_eq!(linear_to_srgb16(srgb16_to_linear(c)), c);
+        }
+    }
+
+    #[test]
+    fn is_monotonic() {
+        for c in 0..255 {
+            assert!(srgb_to_linear(c) < srgb_to_linear(c + 1));
+        }
+        assert_eq!(srgb_to_linear(0), 0.);
+        assert!((srgb_to_linear(255) - 1.).abs() < f32::EPSILON);
+    }
+}
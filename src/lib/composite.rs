@@ -0,0 +1,329 @@
+use image::{ColorType, DynamicImage, ImageBuffer, Pixel, Primitive};
+use rayon::prelude::*;
+
+use crate::{
+    color::{linear_to_srgb, linear_to_srgb16, srgb16_to_linear, srgb_to_linear},
+    projector::Blending,
+    warp::{Canvas, ROWS_PER_CHUNK},
+};
+
+/// Alpha-composite `top` over `base` with its top left corner at `(x, y)`,
+/// like `imageops::overlay`. Chunks of rows are composited in parallel.
+///
+/// 8 and 16-bit RGB and RGBA bases are blended directly. Other bases are
+/// blended as 16-bit RGB or RGBA, and converted back to their own color type.
+pub fn overlay(base: &mut DynamicImage, top: &Canvas, x: i64, y: i64) {
+    match base {
+        DynamicImage::ImageRgb8(base) => overlay_into(base, top, x, y),
+        DynamicImage::ImageRgba8(base) => overlay_into(base, top, x, y),
+        DynamicImage::ImageRgb16(base) => overlay_into(base, top, x, y),
+        DynamicImage::ImageRgba16(base) => overlay_into(base, top, x, y),
+        base => {
+            let color = base.color();
+            let mut wide = if color.has_alpha() {
+                DynamicImage::ImageRgba16(base.to_rgba16())
+            } else {
+                DynamicImage::ImageRgb16(base.to_rgb16())
+            };
+            overlay(&mut wide, top, x, y);
+            *base = match color {
+                ColorType::L8 => DynamicImage::ImageLuma8(wide.to_luma8()),
+                ColorType::La8 => DynamicImage::ImageLumaA8(wide.to_luma_alpha8()),
+                ColorType::L16 => DynamicImage::ImageLuma16(wide.to_luma16()),
+                ColorType::La16 => DynamicImage::ImageLumaA16(wide.to_luma_alpha16()),
+                ColorType::Rgb32F => DynamicImage::ImageRgb32F(wide.to_rgb32f()),
+                ColorType::Rgba32F => DynamicImage::ImageRgba32F(wide.to_rgba32f()),
+                _ => wide,
+            };
+        }
+    }
+}
+
+// A channel of a base image, in sRGB.
+trait Channel: Primitive {
+    const MAX: f32;
+
+    fn value(self) -> f32;
+    // rounded to the nearest value
+    fn from_value(value: f32) -> Self;
+    fn to_linear(self) -> f32;
+    fn from_linear(linear: f32) -> Self;
+}
+
+impl Channel for u8 {
+    const MAX: f32 = 255.;
+
+    fn value(self) -> f32 {
+        f32::from(self)
+    }
+
+    fn from_value(value: f32) -> Self {
+        value.round() as u8
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb_to_linear(self)
+    }
+
+    fn from_linear(linear: f32) -> Self {
+        linear_to_srgb(linear)
+    }
+}
+
+impl Channel for u16 {
+    const MAX: f32 = 65535.;
+
+    fn value(self) -> f32 {
+        f32::from(self)
+    }
+
+    fn from_value(value: f32) -> Self {
+        value.round() as u16
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb16_to_linear(self)
+    }
+
+    fn from_linear(linear: f32) -> Self {
+        linear_to_srgb16(linear)
+    }
+}
+
+fn overlay_into<P, C>(base: &mut ImageBuffer<P, Vec<C>>, top: &Canvas, x: i64, y: i64)
+where
+    P: Pixel<Subpixel = C>,
+    C: Channel + Send + Sync,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let row_len = base.width() as usize * channels;
+    // the part of base covered by top
+    let (left, right) = (
+        x.max(0),
+        (x + i64::from(top.width)).min(i64::from(base.width())),
+    );
+    let (upper, lower) = (
+        y.max(0),
+        (y + i64::from(top.height)).min(i64::from(base.height())),
+    );
+    if left >= right || upper >= lower {
+        return;
+    }
+
+    let buffer: &mut [C] = &mut *base;
+    buffer[upper as usize * row_len..lower as usize * row_len]
+        .par_chunks_mut(row_len * ROWS_PER_CHUNK)
+        .enumerate()
+        .for_each(|(chunk, rows)| {
+            for (i, row) in rows.chunks_mut(row_len).enumerate() {
+                let top_y = (upper + (chunk * ROWS_PER_CHUNK + i) as i64 - y) as usize;
+                for base_x in left..right {
+                    let top_x = (base_x - x) as usize;
+                    let base_x = base_x as usize;
+                    let pixel = &mut row[base_x * channels..(base_x + 1) * channels];
+                    let top_pixel = top.get(top_x as u32, top_y as u32);
+                    match top.blending {
+                        Blending::Srgb => blend_srgb(pixel, top_pixel),
+                        Blending::Linear => blend_linear(pixel, top_pixel),
+                    }
+                }
+            }
+        });
+}
+
+// Blend a straight-alpha 8-bit sRGB pixel over an RGB or RGBA one, in sRGB.
+fn blend_srgb<C: Channel>(base: &mut [C], top: [f32; 4]) {
+    let top = top.map(|c| c.round().clamp(0., 255.));
+    let alpha = top[3] / 255.;
+    if alpha == 0. {
+        return;
+    }
+    let scale = C::MAX / 255.;
+    let base_alpha = base.get(3).map_or(1., |alpha| alpha.value() / C::MAX);
+    let out_alpha = alpha + base_alpha * (1. - alpha);
+    for (base, top) in base.iter_mut().zip(top).take(3) {
+        let color = top * scale * alpha + base.value() * base_alpha * (1. - alpha);
+        *base = C::from_value(color / out_alpha);
+    }
+    if let Some(base_alpha) = base.get_mut(3) {
+        *base_alpha = C::from_value(out_alpha * C::MAX);
+    }
+}
+
+// Blend a premultiplied linear pixel over an RGB or RGBA one, in linear light.
+fn blend_linear<C: Channel>(base: &mut [C], top: [f32; 4]) {
+    let alpha = top[3];
+    if alpha <= 0. {
+        return;
+    }
+    let base_alpha = base.get(3).map_or(1., |alpha| alpha.value() / C::MAX);
+    let out_alpha = alpha + base_alpha * (1. - alpha);
+    for (base, top) in base.iter_mut().zip(top).take(3) {
+        let color = top + base.to_linear() * base_alpha * (1. - alpha);
+        *base = C::from_linear(color / out_alpha);
+    }
+    if let Some(base_alpha) = base.get_mut(3) {
+        *base_alpha = C::from_value(out_alpha * C::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{
+        imageops,
+        ColorType,
+        DynamicImage,
+        ImageBuffer,
+        Luma,
+        Rgb,
+        RgbImage,
+        Rgba,
+        RgbaImage,
+    };
+
+    use super::{blend_linear, blend_srgb, overlay};
+    use crate::{color::srgb_to_linear, projector::Blending, warp::Canvas};
+
+    // taller than a chunk of rows, and not a multiple of one
+    const BASE_SIZE: (u32, u32) = (40, 37);
+    const TOP_SIZE: (u32, u32) = (23, 21);
+    const OFFSETS: [(i64, i64); 7] = [
+        (0, 0),
+        (8, 3),
+        (-5, -7),
+        (30, 20),
+        (-10, 25),
+        (-30, -30),
+        (50, 0),
+    ];
+
+    fn canvas(blending: Blending, color: impl Fn(u32, u32) -> Rgba<u8>) -> Canvas {
+        let mut canvas = Canvas::new(TOP_SIZE.0, TOP_SIZE.1, blending);
+        for (i, pixel) in canvas.pixels.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % TOP_SIZE.0, i as u32 / TOP_SIZE.0);
+            let Rgba([r, g, b, a]) = color(x, y);
+            let channels = match blending {
+                Blending::Srgb => [r, g, b, a].map(f32::from),
+                Blending::Linear => {
+                    let alpha = f32::from(a) / 255.;
+                    [
+                        srgb_to_linear(r) * alpha,
+                        srgb_to_linear(g) * alpha,
+                        srgb_to_linear(b) * alpha,
+                        alpha,
+                    ]
+                }
+            };
+            *pixel = Canvas::encode(blending, channels);
+        }
+        canvas
+    }
+
+    fn bases() -> Vec<DynamicImage> {
+        let (width, height) = BASE_SIZE;
+        let rgb = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([(x * 6) as u8, (y * 6) as u8, ((x + y) * 3) as u8])
+        });
+        let rgba = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(y * 6) as u8, (x * 6) as u8, 128, ((x * y) % 256) as u8])
+        });
+        vec![
+            DynamicImage::ImageRgb16(DynamicImage::ImageRgb8(rgb.clone()).to_rgb16()),
+            DynamicImage::ImageRgba16(DynamicImage::ImageRgba8(rgba.clone()).to_rgba16()),
+            DynamicImage::ImageRgb8(rgb),
+            DynamicImage::ImageRgba8(rgba),
+        ]
+    }
+
+    // Overlay `top` like `imageops::overlay` would, and compare.
+    fn assert_overlays_like_imageops(top: &Canvas) {
+        for base in bases() {
+            for (x, y) in OFFSETS {
+                let mut expected = base.clone();
+                imageops::overlay(&mut expected, &top.to_rgba8(), x, y);
+                let mut actual = base.clone();
+                overlay(&mut actual, top, x, y);
+
+                assert_eq!(actual.color(), base.color());
+                for ((expected, actual), (px, py)) in expected
+                    .to_rgba8()
+                    .pixels()
+                    .zip(actual.to_rgba8().pixels())
+                    .zip((0..BASE_SIZE.1).flat_map(|y| (0..BASE_SIZE.0).map(move |x| (x, y))))
+                {
+                    // imageops truncates where the blend here rounds
+                    assert!(
+                        expected
+                            .0
+                            .iter()
+                            .zip(actual.0)
+                            .all(|(e, a)| e.abs_diff(a) <= 1),
+                        "{:?} at ({}, {}) overlaid at ({}, {}) is {:?}, not {:?}",
+                        base.color(),
+                        px,
+                        py,
+                        x,
+                        y,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_overlays_like_imageops() {
+        assert_overlays_like_imageops(&canvas(Blending::Srgb, |x, y| {
+            Rgba([
+                (x * 11) as u8,
+                (y * 12) as u8,
+                200,
+                ((x * 37 + y * 91) % 256) as u8,
+            ])
+        }));
+    }
+
+    // blending in linear light only differs for partly covered pixels
+    #[test]
+    fn linear_overlays_opaque_like_imageops() {
+        assert_overlays_like_imageops(&canvas(Blending::Linear, |x, y| {
+            let alpha = if (x + y) % 3 == 0 { 0 } else { 255 };
+            Rgba([(x * 11) as u8, (y * 12) as u8, 200, alpha])
+        }));
+    }
+
+    #[test]
+    fn other_bases_keep_their_color_type() {
+        let top = canvas(Blending::Linear, |x, y| {
+            Rgba([
+                (x * 11) as u8,
+                (y * 12) as u8,
+                200,
+                ((x * 37 + y * 91) % 256) as u8,
+            ])
+        });
+        let gray =
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(BASE_SIZE.0, BASE_SIZE.1, |x, y| {
+                Luma([(x * 1500 + y * 100) as u16])
+            }));
+        let mut expected = DynamicImage::ImageRgb16(gray.to_rgb16());
+        overlay(&mut expected, &top, 8, 3);
+        let mut actual = gray;
+        overlay(&mut actual, &top, 8, 3);
+        assert_eq!(actual.color(), ColorType::L16);
+        assert_eq!(actual, DynamicImage::ImageLuma16(expected.to_luma16()));
+    }
+
+    // half covered black over white, which is darker when blended in sRGB
+    #[test]
+    fn blends_coverage_in_linear_light() {
+        let mut base = [255_u8; 3];
+        blend_linear(&mut base, [0., 0., 0., 0.5]);
+        assert_eq!(base, [188; 3]);
+
+        let mut base = [255_u8; 3];
+        blend_srgb(&mut base, [0., 0., 0., 127.5]);
+        assert_eq!(base, [127; 3]);
+    }
+}
@@ -1,5 +1,7 @@
 use image::DynamicImage;
-pub use weeb::WeebDetector;
+#[cfg(feature = "bench")]
+pub use weeb::bench;
+pub use weeb::{ModelSource, WeebDetector};
 
 use crate::errors::Result;
 // pub use standard::StandardDetector;
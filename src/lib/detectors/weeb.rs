@@ -2,9 +2,11 @@ use std::{
     cell::RefCell,
     cmp::{max, min, Ordering},
     convert::TryInto,
+    io::{self, Read},
+    path::Path,
 };
 
-use image::{imageops, imageops::FilterType, DynamicImage};
+use image::{imageops, imageops::FilterType, DynamicImage, RgbImage};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use mcai_onnxruntime::{
@@ -26,12 +28,13 @@ use ndarray::{
 use nshare::ToNdarray3;
 use num::{Num, NumCast};
 use opencv::{
-    core::{Rect, Size},
+    core::{FileStorage, FileStorage_Mode, Rect, Size},
     objdetect::{CascadeClassifier, CascadeClassifierTrait},
     prelude::*,
     types::VectorOfRect,
 };
 use tap::Pipe;
+use tracing::{debug_span, Span};
 
 use crate::{
     convert::img_to_mat,
@@ -52,20 +55,199 @@ pub struct WeebDetector<'a> {
     landmark_detector: RefCell<Session<'a>>,
 }
 
+/// Where a model is loaded from.
+pub enum ModelSource<'m> {
+    Path(&'m Path),
+    Bytes(&'m [u8]),
+    Reader(Box<dyn Read + 'm>),
+}
+
+impl<'m> From<&'m Path> for ModelSource<'m> {
+    fn from(path: &'m Path) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl<'m> From<&'m [u8]> for ModelSource<'m> {
+    fn from(bytes: &'m [u8]) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+fn invalid_face_model() -> Error {
+    Error::IOError(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid face model",
+    ))
+}
+
+fn load_face_detector(source: ModelSource) -> Result<CascadeClassifier> {
+    let model = match source {
+        // loading from a file also takes cascades in the old format, which
+        // reading from memory doesn't
+        ModelSource::Path(path) => {
+            // opencv takes the path as a string, a lossy one would name another file
+            let path = path.to_str().ok_or_else(|| {
+                Error::IOError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("face model path {} is not valid UTF-8", path.display()),
+                ))
+            })?;
+            let face_detector = CascadeClassifier::new(path)?;
+            return if face_detector.empty()? {
+                Err(invalid_face_model())
+            } else {
+                Ok(face_detector)
+            };
+        }
+        ModelSource::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+            .map_err(|e| Error::IOError(io::Error::new(io::ErrorKind::InvalidData, e)))?,
+        ModelSource::Reader(mut reader) => {
+            let mut model = String::new();
+            reader.read_to_string(&mut model)?;
+            model
+        }
+    };
+    let storage = FileStorage::new(
+        &model,
+        FileStorage_Mode::READ as i32 | FileStorage_Mode::MEMORY as i32,
+        "",
+    )?;
+    let mut face_detector = CascadeClassifier::default()?;
+    if !face_detector.read(&storage.get_first_top_level_node()?)? {
+        return Err(invalid_face_model());
+    }
+    Ok(face_detector)
+}
+
+fn load_landmark_detector(source: ModelSource) -> Result<Session<'static>> {
+    let builder = ENV.new_session_builder()?;
+    let session = match source {
+        ModelSource::Path(path) => builder.with_model_from_file(path)?,
+        ModelSource::Bytes(bytes) => builder.with_model_from_memory(bytes)?,
+        ModelSource::Reader(mut reader) => {
+            let mut model = Vec::new();
+            reader.read_to_end(&mut model)?;
+            builder.with_model_from_memory(model)?
+        }
+    };
+    Ok(session)
+}
+
 impl<'a> WeebDetector<'a> {
-    pub fn new(face_model: &str, landmark_model: &str) -> Result<Self> {
-        #[allow(clippy::unnecessary_to_owned)]
-        let session: Session = ENV
-            .new_session_builder()?
-            .with_model_from_file(landmark_model.to_string())?;
+    pub fn new(face_model: impl AsRef<Path>, landmark_model: impl AsRef<Path>) -> Result<Self> {
+        Self::from_sources(face_model.as_ref().into(), landmark_model.as_ref().into())
+    }
+
+    /// Load both models from memory, e.g. buffers embedded into the binary.
+    pub fn from_bytes(face_model: &[u8], landmark_model: &[u8]) -> Result<Self> {
+        Self::from_sources(face_model.into(), landmark_model.into())
+    }
+
+    /// Load both models from readers, e.g. entries of an archive.
+    pub fn from_readers(face_model: impl Read, landmark_model: impl Read) -> Result<Self> {
+        Self::from_sources(
+            ModelSource::Reader(Box::new(face_model)),
+            ModelSource::Reader(Box::new(landmark_model)),
+        )
+    }
+
+    /// Load each model from wherever it's kept.
+    pub fn from_sources(face_model: ModelSource, landmark_model: ModelSource) -> Result<Self> {
         Ok(Self {
-            face_detector: RefCell::new(CascadeClassifier::new(face_model).unwrap()),
-            landmark_detector: RefCell::new(session),
+            face_detector: RefCell::new(load_face_detector(face_model)?),
+            landmark_detector: RefCell::new(load_landmark_detector(landmark_model)?),
         })
     }
+
+    /// Load the models embedded into the binary by the `embed-models` feature.
+    #[cfg(feature = "embed-models")]
+    pub fn embedded() -> Result<Self> {
+        Self::from_bytes(
+            crate::models::EMBEDDED_FACE_MODEL,
+            crate::models::EMBEDDED_LANDMARK_MODEL,
+        )
+    }
+}
+
+impl WeebDetector<'_> {
+    // Faces found by the cascade classifier.
+    fn detect_faces(&self, image: &RgbImage) -> Result<Vec<Rect>> {
+        // convert rust image to matrix
+        let image_mat = img_to_mat(image)?;
+
+        // detect face position using pretrained cascade classifier
+        let mut cv_faces = VectorOfRect::new();
+        debug_span!("face_detection").in_scope(|| {
+            self.face_detector.borrow_mut().detect_multi_scale(
+                &image_mat,
+                &mut cv_faces,
+                1.1,
+                3,
+                0,
+                Size::new(0, 0),
+                Size::new(0, 0),
+            )
+        })?;
+        Ok(cv_faces.to_vec())
+    }
+
+    // Mouth landmarks of `face`, predicted by the landmark model.
+    fn detect_landmarks(&self, image: &RgbImage, face: &Rect) -> Result<ControlPoints<f32>> {
+        // slightly enlarge roi
+        let face_rect = face_to_roi(image.width() as i32, face);
+
+        // crop image and convert into matrix
+        let face = image
+            .pipe(|img| imageops::crop_imm(img, face_rect.x, face_rect.y, face_rect.w, face_rect.h))
+            .pipe(|img| imageops::resize(&*img, 128, 128, FilterType::Lanczos3));
+        let face_array = face.into_ndarray3().mapv(|i| i as f32);
+
+        // normalize matrix
+        let nn_input = face_to_nn_input(face_array);
+        let input_tensor = InputTensor::from_array(nn_input);
+
+        // predict landmarks using pretrained model (onnxruntime)
+        let mut landmark_detector = self.landmark_detector.borrow_mut();
+        let nn_outputs: Vec<OrtOwnedTensor<f32, _>> = debug_span!(
+            "landmark_inference",
+            face_width = face_rect.w,
+            face_height = face_rect.h
+        )
+        .in_scope(|| landmark_detector.run(vec![input_tensor]))?;
+
+        // extract the latest stage
+        let nn_output = nn_outputs.into_iter().last().unwrap();
+        let heatmap = nn_output.index_axis(Axis(0), 0);
+
+        // find the most probable coords for mouth landmarks from heatmap
+        let landmarks: Vec<_> = heatmap
+            .axis_iter(Axis(0))
+            .dropping(20)
+            .take(4)
+            .map(|x| argmax(&x))
+            .map(|(x, y)| Point::new(x as u32, y as u32))
+            .collect();
+
+        // rebase the coords
+        let base_landmarks: ControlPoints<f32> = landmarks
+            .iter()
+            .map(|point| rebase(*point, &Rectangle::new(0, 0, 128, 128), &face_rect))
+            .map(|point| Point::new(point.x as f32, point.y as f32))
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+
+        Ok(base_landmarks)
+    }
 }
 
 impl MouthDetectorTrait for WeebDetector<'_> {
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(width = image.width(), height = image.height(), faces)
+    )]
     fn detect(&self, image: &DynamicImage) -> Result<ControlPoints<f32>> {
         let buffer;
         #[allow(clippy::option_if_let_else)]
@@ -75,71 +257,15 @@ impl MouthDetectorTrait for WeebDetector<'_> {
             buffer = image.to_rgb8();
             &buffer
         };
-        // convert rust image to matrix
-        let image_mat = img_to_mat(image)?;
 
-        // detect face position using pretrained cascade classifier
-        let mut cv_faces = VectorOfRect::new();
-        self.face_detector.borrow_mut().detect_multi_scale(
-            &image_mat,
-            &mut cv_faces,
-            1.1,
-            3,
-            0,
-            Size::new(0, 0),
-            Size::new(0, 0),
-        )?;
-
-        let cv_faces = cv_faces.to_vec();
-        (!cv_faces.is_empty())
-            .then(|| -> Result<_> {
-                // find largest face and slightly enlarge roi
-                let cv_face = cv_faces.iter().max_by_key(|rect| rect.area()).unwrap();
-                let face_rect = face_to_roi(image_mat.size()?.width, cv_face);
-
-                // crop image and convert into matrix
-                let face = image
-                    .pipe(|img| {
-                        imageops::crop_imm(img, face_rect.x, face_rect.y, face_rect.w, face_rect.h)
-                    })
-                    .pipe(|img| imageops::resize(&*img, 128, 128, FilterType::Lanczos3));
-                let face_array = face.into_ndarray3().mapv(|i| i as f32);
-
-                // normalize matrix
-                let nn_input = face_to_nn_input(face_array);
-                let input_tensor = InputTensor::from_array(nn_input);
-
-                // predict landmarks using pretrained model (onnxruntime)
-                let mut landmark_detector = self.landmark_detector.borrow_mut();
-                let nn_outputs: Vec<OrtOwnedTensor<f32, _>> =
-                    landmark_detector.run(vec![input_tensor])?;
-
-                // extract the latest stage
-                let nn_output = nn_outputs.into_iter().last().unwrap();
-                let heatmap = nn_output.index_axis(Axis(0), 0);
-
-                // find the most probable coords for mouth landmarks from heatmap
-                let landmarks: Vec<_> = heatmap
-                    .axis_iter(Axis(0))
-                    .dropping(20)
-                    .take(4)
-                    .map(|x| argmax(&x))
-                    .map(|(x, y)| Point::new(x as u32, y as u32))
-                    .collect();
-
-                // rebase the coords
-                let base_landmarks: ControlPoints<f32> = landmarks
-                    .iter()
-                    .map(|point| rebase(*point, &Rectangle::new(0, 0, 128, 128), &face_rect))
-                    .map(|point| Point::new(point.x as f32, point.y as f32))
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .unwrap();
-
-                Ok(base_landmarks)
-            })
-            .ok_or(Error::NoneError)
-            .and_then(|x| x) // .flatten() is gated by `result_flattening`
+        let faces = self.detect_faces(image)?;
+        Span::current().record("faces", faces.len());
+        // find largest face
+        let face = faces
+            .iter()
+            .max_by_key(|rect| rect.area())
+            .ok_or(Error::NoneError)?;
+        self.detect_landmarks(image, face)
     }
 }
 
@@ -164,7 +290,7 @@ fn rebase<T: Num + NumCast + PartialOrd + Copy>(
     }
 }
 
-fn argmax<T: RemoveAxis>(array: &ArrayBase<ViewRepr<&f32>, T>) -> (usize, usize) {
+pub fn argmax<T: RemoveAxis>(array: &ArrayBase<ViewRepr<&f32>, T>) -> (usize, usize) {
     array
         .axis_iter(Axis(0))
         .into_par_iter()
@@ -182,7 +308,7 @@ fn argmax<T: RemoveAxis>(array: &ArrayBase<ViewRepr<&f32>, T>) -> (usize, usize)
         .unwrap()
 }
 
-fn face_to_nn_input(
+pub fn face_to_nn_input(
     mut face_array: ArrayBase<OwnedRepr<f32>, Ix3>,
 ) -> ArrayBase<OwnedRepr<f32>, Ix4> {
     let min_diff: Vec<_> = face_array
@@ -228,3 +354,27 @@ fn face_to_roi<T: Num + NumCast + PartialOrd + Copy>(
     let h = by - y;
     Rectangle::new(cast!(x), cast!(y), cast!(w), cast!(h))
 }
+
+// The stages of the detector, so that they can be benchmarked one by one.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    use image::RgbImage;
+    use opencv::core::Rect;
+
+    use super::WeebDetector;
+    pub use super::{argmax, face_to_nn_input};
+    use crate::{errors::Result, types::ControlPoints};
+
+    pub fn detect_faces(detector: &WeebDetector, image: &RgbImage) -> Result<Vec<Rect>> {
+        detector.detect_faces(image)
+    }
+
+    pub fn detect_landmarks(
+        detector: &WeebDetector,
+        image: &RgbImage,
+        face: &Rect,
+    ) -> Result<ControlPoints<f32>> {
+        detector.detect_landmarks(image, face)
+    }
+}
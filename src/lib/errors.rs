@@ -16,6 +16,14 @@ pub enum Error {
     IOError(#[from] io::Error),
     #[error("math error: {0}")]
     MathError(String),
+    #[error("unsupported image format")]
+    UnsupportedFormat,
+    #[error("http error: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+    #[error("model {0} is missing or corrupt, and downloading is disabled in offline mode")]
+    Offline(String),
     #[error("internal error for None")]
     NoneError,
 }
@@ -13,7 +13,12 @@
 #[macro_use]
 mod types;
 pub mod ciyafier;
+pub mod codec;
+mod color;
+mod composite;
 mod convert;
 pub mod detectors;
 pub mod errors;
-mod projector;
+pub mod models;
+pub mod projector;
+mod warp;
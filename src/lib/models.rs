@@ -0,0 +1,499 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    iter,
+    path::{Path, PathBuf},
+    process,
+    thread,
+    time::Duration,
+};
+
+use fs2::FileExt;
+use reqwest::{
+    blocking::{Client, Response},
+    header::{CONTENT_RANGE, RANGE},
+    Proxy,
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+use crate::errors::{Error, Result};
+
+const MODEL_DIR_ENV: &str = "CIYA_MODEL_DIR";
+
+/// A model file detectors depend on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Model {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub file_name: &'static str,
+    pub url: &'static str,
+    /// Expected SHA-256 of the file. If unknown, the hash of the first
+    /// download is pinned, and later loads and downloads are verified against
+    /// it. That first download is always from `url`, never from a mirror.
+    pub sha256: Option<&'static str>,
+}
+
+pub const FACE_MODEL: Model = Model {
+    name: "lbpcascade_animeface",
+    version: "1",
+    file_name: "lbpcascade_animeface.xml",
+    url: "https://raw.githubusercontent.com/nagadomi/lbpcascade_animeface/master/lbpcascade_animeface.xml",
+    sha256: Some("9376d30ac38db6bda2a68b88b3b76bbd7e6aa33af47f7f5c76bc88ca75f1ce30"),
+};
+
+pub const LANDMARK_MODEL: Model = Model {
+    name: "anime_face_landmark",
+    version: "0.0.1",
+    file_name: "anime_face_landmark.onnx",
+    url:
+        "https://github.com/PhotonQuantum/ciya-rs/releases/download/v0.0.1/anime_face_landmark.onnx",
+    sha256: None,
+};
+
+/// Every model known to this version of the library.
+pub const MANIFEST: &[Model] = &[FACE_MODEL, LANDMARK_MODEL];
+
+/// `FACE_MODEL`, embedded into the binary.
+#[cfg(feature = "embed-models")]
+pub const EMBEDDED_FACE_MODEL: &[u8] = include_bytes!("../../resources/lbpcascade_animeface.xml");
+/// `LANDMARK_MODEL`, embedded into the binary. Run `make fetch-models` to
+/// download it first.
+#[cfg(feature = "embed-models")]
+pub const EMBEDDED_LANDMARK_MODEL: &[u8] =
+    include_bytes!("../../resources/anime_face_landmark.onnx");
+
+/// How far the download of a model has got.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Progress {
+    pub downloaded: u64,
+    /// Size of the whole file, if the server tells.
+    pub total: Option<u64>,
+}
+
+type ProgressFn = dyn Fn(&Model, Progress) + Send + Sync;
+
+/// Keeps models under `root/<name>/<version>/`, so that different versions
+/// of a model live side by side.
+pub struct ModelManager {
+    root: PathBuf,
+    // a directory whose loose model files take precedence over managed ones
+    local_dir: Option<PathBuf>,
+    offline: bool,
+    mirrors: Vec<String>,
+    proxy: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    progress: Option<Box<ProgressFn>>,
+}
+
+impl ModelManager {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            local_dir: None,
+            offline: false,
+            mirrors: vec![],
+            proxy: None,
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            progress: None,
+        }
+    }
+
+    /// A manager rooted at `$CIYA_MODEL_DIR` if set. Otherwise it's rooted at
+    /// `ciya-rs/models` in the local data directory, and model files in the
+    /// current directory take precedence.
+    pub fn with_default_root() -> Result<Self> {
+        if let Some(root) = env::var_os(MODEL_DIR_ENV) {
+            return Ok(Self::new(root));
+        }
+        let data_dir = dirs::data_local_dir().ok_or_else(|| {
+            Error::IOError(io::Error::new(
+                io::ErrorKind::NotFound,
+                "missing local data directory",
+            ))
+        })?;
+        let mut manager = Self::new(data_dir.join("ciya-rs").join("models"));
+        manager.local_dir = Some(env::current_dir()?);
+        Ok(manager)
+    }
+
+    /// Fail instead of downloading missing or corrupt models.
+    #[must_use]
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Try these base URLs before the upstream one of each model. A model is
+    /// expected at `<mirror>/<name>/<version>/<file_name>`.
+    #[must_use]
+    pub fn mirrors<I>(mut self, mirrors: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.mirrors = mirrors.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Download through this proxy, instead of the one from `HTTPS_PROXY` and
+    /// friends.
+    #[must_use]
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Give up on a connection, or a read from it, after `timeout`.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry failed downloads from each URL `retries` times, waiting `backoff`
+    /// before the first retry and twice as long before each following one.
+    #[must_use]
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Call `progress` whenever a chunk of a model is downloaded.
+    #[must_use]
+    pub fn on_progress(
+        mut self,
+        progress: impl Fn(&Model, Progress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where `model` is stored, whether it exists or not.
+    pub fn path(&self, model: &Model) -> PathBuf {
+        self.root
+            .join(model.name)
+            .join(model.version)
+            .join(model.file_name)
+    }
+
+    fn pin_path(&self, model: &Model) -> PathBuf {
+        self.path(model)
+            .with_file_name(format!("{}.sha256", model.file_name))
+    }
+
+    fn expected_hash(&self, model: &Model) -> Result<Option<String>> {
+        if let Some(hash) = model.sha256 {
+            return Ok(Some(hash.to_string()));
+        }
+        match fs::read_to_string(self.pin_path(model)) {
+            Ok(hash) => Ok(Some(hash.trim().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Check the stored `model` against its expected or pinned hash.
+    pub fn verify(&self, model: &Model) -> Result<()> {
+        let path = self.path(model);
+        let hash = sha256_file(&path)?;
+        match self.expected_hash(model)? {
+            Some(expected) if expected == hash => Ok(()),
+            _ => Err(Error::ChecksumMismatch(path.display().to_string())),
+        }
+    }
+
+    fn client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .connect_timeout(self.timeout)
+            .timeout(self.timeout);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        Ok(builder.build()?)
+    }
+
+    // The mirrors of `model`, followed by its upstream URL.
+    fn urls(&self, model: &Model) -> Vec<String> {
+        self.mirrors
+            .iter()
+            .map(|mirror| {
+                format!(
+                    "{}/{}/{}/{}",
+                    mirror.trim_end_matches('/'),
+                    model.name,
+                    model.version,
+                    model.file_name
+                )
+            })
+            .chain(iter::once(model.url.to_string()))
+            .collect()
+    }
+
+    /// Where the download of `model` from `url` is kept until it's complete.
+    pub fn partial_path(&self, model: &Model, url: &str) -> PathBuf {
+        let url_hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+        self.path(model)
+            .with_file_name(format!("{}.{}.part", model.file_name, &url_hash[..16]))
+    }
+
+    fn lock_path(&self, model: &Model) -> PathBuf {
+        self.path(model)
+            .with_file_name(format!("{}.lock", model.file_name))
+    }
+
+    /// Download `model`, replacing the stored one.
+    ///
+    /// The file is downloaded next to its final path and only renamed into
+    /// place once its hash is verified, so an interrupted download never
+    /// leaves a corrupt model behind. Instead, the next download from the same
+    /// URL resumes where it stopped. Downloads of a model are locked, so that
+    /// processes sharing the model directory wait for each other instead of
+    /// writing to it at once.
+    pub fn fetch(&self, model: &Model) -> Result<PathBuf> {
+        self.fetch_locked(model, false)
+    }
+
+    // Take the lock of `model`, then download it unless `keep_verified` and
+    // whoever held the lock before left a verified copy behind.
+    fn fetch_locked(&self, model: &Model, keep_verified: bool) -> Result<PathBuf> {
+        if self.offline {
+            return Err(Error::Offline(model.name.to_string()));
+        }
+        let path = self.path(model);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.lock_path(model))?;
+        lock.lock_exclusive()?;
+        if keep_verified && self.verify(model).is_ok() {
+            return Ok(path);
+        }
+
+        // a download replacing a corrupt model must match the pin of the
+        // original one, so a pin is never replaced
+        let expected = self.expected_hash(model)?;
+        // whatever is downloaded without a hash to check is pinned, so it's
+        // only trusted from upstream
+        let urls = match expected {
+            Some(_) => self.urls(model),
+            None => vec![model.url.to_string()],
+        };
+        let client = self.client()?;
+        let mut last_error = None;
+        for url in urls {
+            match self.fetch_from(&client, model, &url, expected.as_deref()) {
+                Ok(()) => return Ok(path),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    // Download `model` from `url` and move it into place if it matches
+    // `expected`, or pin it if nothing is expected.
+    fn fetch_from(
+        &self,
+        client: &Client,
+        model: &Model,
+        url: &str,
+        expected: Option<&str>,
+    ) -> Result<()> {
+        let temp = self.partial_path(model, url);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&temp)?;
+
+        let result = self
+            .download_with_retries(client, model, url, &mut file)
+            .and_then(|hash| match expected {
+                Some(expected) if expected != hash => Err(Error::ChecksumMismatch(url.to_string())),
+                Some(_) => Ok(()),
+                None => write_atomically(&self.pin_path(model), hash.as_bytes()),
+            })
+            // renamed while still locked, so nobody resumes a finished download
+            .and_then(|()| fs::rename(&temp, self.path(model)).map_err(Error::from));
+        if result.is_err() {
+            // the next URL starts over, instead of appending to this one
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn download_with_retries(
+        &self,
+        client: &Client,
+        model: &Model,
+        url: &str,
+        file: &mut File,
+    ) -> Result<String> {
+        let mut backoff = self.backoff;
+        let mut retries = self.retries;
+        loop {
+            match self.download(client, model, url, file) {
+                Err(e) if retries > 0 && is_transient(&e) => {
+                    retries -= 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Download `url` into `file`, returning the hash of its content. If `file`
+    // holds part of it already, only the rest is downloaded.
+    fn download(
+        &self,
+        client: &Client,
+        model: &Model,
+        url: &str,
+        file: &mut File,
+    ) -> Result<String> {
+        let offset = file.metadata()?.len();
+        let mut request = client.get(url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let response = request.send()?;
+        let range = content_range(&response);
+        if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // interrupted after the last byte, but before being renamed
+            if matches!(range, Some((_, Some(total))) if total == offset) {
+                file.seek(SeekFrom::Start(0))?;
+                return hash_content(file);
+            }
+            // the partial file is no prefix of this one, start over
+            file.set_len(0)?;
+            return self.download(client, model, url, file);
+        }
+        let mut response = response.error_for_status()?;
+
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed && !matches!(range, Some((Some(start), _)) if start == offset) {
+            // not the part that was asked for
+            file.set_len(0)?;
+            return self.download(client, model, url, file);
+        }
+        let mut hasher = Sha256::new();
+        file.seek(SeekFrom::Start(0))?;
+        let mut downloaded = if resumed {
+            io::copy(&mut Read::by_ref(file).take(offset), &mut hasher)?;
+            offset
+        } else {
+            file.set_len(0)?;
+            0
+        };
+        let total = response.content_length().map(|len| len + downloaded);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = response.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            file.write_all(&buffer[..n])?;
+            downloaded += n as u64;
+            if let Some(progress) = &self.progress {
+                progress(model, Progress { downloaded, total });
+            }
+        }
+        file.sync_all()?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Path of a verified copy of `model`, downloading it if it's missing or
+    /// corrupt.
+    pub fn ensure(&self, model: &Model) -> Result<PathBuf> {
+        match self.verify(model) {
+            Ok(()) => Ok(self.path(model)),
+            // another process may be downloading it already
+            Err(_) => self.fetch_locked(model, true),
+        }
+    }
+
+    /// Paths of the face and landmark models `WeebDetector` needs.
+    pub fn ensure_weeb(&self) -> Result<(PathBuf, PathBuf)> {
+        if let Some(local_dir) = &self.local_dir {
+            let local = (
+                local_dir.join(FACE_MODEL.file_name),
+                local_dir.join(LANDMARK_MODEL.file_name),
+            );
+            if local.0.is_file() && local.1.is_file() {
+                return Ok(local);
+            }
+        }
+        Ok((self.ensure(&FACE_MODEL)?, self.ensure(&LANDMARK_MODEL)?))
+    }
+}
+
+// Whether retrying might help.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::HttpError(e) => e.status().is_none_or(|status| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        }),
+        // the body failed to arrive, which reqwest reports as an io::Error,
+        // unlike failures to write it to disk
+        Error::IOError(e) => {
+            e.get_ref()
+                .is_some_and(|inner| inner.is::<reqwest::Error>())
+                || matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::Interrupted
+                )
+        }
+        _ => false,
+    }
+}
+
+// The first byte and the complete length given by the `Content-Range` header
+// of `response`, e.g. `bytes 100-199/200` or `bytes */200`.
+fn content_range(response: &Response) -> Option<(Option<u64>, Option<u64>)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range
+        .split_once('-')
+        .and_then(|(start, _)| start.parse().ok());
+    Some((start, total.parse().ok()))
+}
+
+fn hash_content(mut reader: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    hash_content(File::open(path)?)
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let temp = path.with_file_name(format!(
+        "{}.{}.part",
+        path.file_name().unwrap().to_string_lossy(),
+        process::id()
+    ));
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
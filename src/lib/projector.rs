@@ -1,33 +1,44 @@
 use std::io::Cursor;
 
-use image::{
-    imageops::{self, FilterType},
-    io::Reader as ImageReader,
-    DynamicImage,
-    ImageFormat,
-    Rgba,
-    RgbaImage,
-};
-use imageproc::geometric_transformations::{Interpolation, Projection};
+use image::{imageops, io::Reader as ImageReader, DynamicImage, ImageFormat, RgbaImage};
+use imageproc::geometric_transformations::Projection;
 use num::{traits::Pow, Num, NumCast};
+use tracing::debug_span;
 
 use crate::{
+    composite::overlay,
     errors::{Error, Result},
     types::{user_abs_minus, ControlPoints, Point, Rectangle},
+    warp::{warp_into, Canvas},
 };
 
 const CIYA_RAW: &[u8] = include_bytes!("../../resources/ciya.png");
 
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum Emotion {
     Auto,
     Smile,
     Cry,
+    /// The opposite of what `Auto` would pick.
+    Flip,
+}
+
+/// How the ciya is interpolated and composited onto the image.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Blending {
+    /// Straight alpha in sRGB, which darkens the edges of the ciya. Kept to
+    /// compare against.
+    Srgb,
+    /// Premultiplied alpha in linear light.
+    #[default]
+    Linear,
 }
 
 pub struct Projector {
     ciya_image: RgbaImage,
     flipped_ciya_image: RgbaImage,
+    blending: Blending,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -36,7 +47,14 @@ enum ProjectionStrategy {
     RespectEdge { smile: bool },
 }
 
+impl Default for Projector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Projector {
+    #[must_use]
     pub fn new() -> Self {
         let mut image_raw = ImageReader::new(Cursor::new(CIYA_RAW));
         image_raw.set_format(ImageFormat::Png);
@@ -45,9 +63,21 @@ impl Projector {
         Self {
             ciya_image: image,
             flipped_ciya_image: flipped_image,
+            blending: Blending::default(),
         }
     }
 
+    #[must_use]
+    pub fn with_blending(mut self, blending: Blending) -> Self {
+        self.blending = blending;
+        self
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip(self, image, control_points),
+        fields(width = image.width(), height = image.height())
+    )]
     pub fn project(
         &self,
         mut image: DynamicImage,
@@ -55,8 +85,19 @@ impl Projector {
         emotion: Emotion,
         antialias_scale: u32,
     ) -> Result<DynamicImage> {
+        if antialias_scale == 0 {
+            return Err(Error::MathError(String::from(
+                "antialias_scale must be positive",
+            )));
+        }
         let smile = is_smile(&control_points)
             .ok_or_else(|| Error::MathError(String::from("invalid control points")))?;
+        // the ciya is fitted between the top and the bottom of the mouth
+        if (control_points.p4.y - control_points.p2.y).abs() < f32::EPSILON {
+            return Err(Error::MathError(String::from(
+                "top and bottom of the mouth are level",
+            )));
+        }
 
         // pick appropriate version of ciya according to emotion.
         let ciya = match emotion {
@@ -69,10 +110,17 @@ impl Projector {
             }
             Emotion::Smile => &self.ciya_image,
             Emotion::Cry => &self.flipped_ciya_image,
+            Emotion::Flip => {
+                if smile {
+                    &self.flipped_ciya_image
+                } else {
+                    &self.ciya_image
+                }
+            }
         };
 
-        let upscale_projection = Projection::scale(antialias_scale as f32, antialias_scale as f32);
         // calculate projection over ciya and overlay position in the target image
+        let control_points_span = debug_span!("control_points", smile).entered();
         let (offset, canvas_size, projection) = if control_points
             .is_convex()
             .ok_or_else(|| Error::MathError(String::from("invalid control points")))?
@@ -96,37 +144,29 @@ impl Projector {
                     Error::MathError(String::from("unable to compute projection matrix"))
                 })
         }
-        .map(|(bound_lt, bound_rb, projection)| {
-            (
-                bound_lt,
-                bound_rb - bound_lt,
-                upscale_projection * projection, // upscale for antialiasing purpose
-            )
-        })?;
-
-        let scaled_size = Point::<u32>::from(&canvas_size);
+        .map(|(bound_lt, bound_rb, projection)| (bound_lt, bound_rb - bound_lt, projection))?;
+        drop(control_points_span);
+
+        // nearly parallel mouth axes put the cross point, and with it the
+        // enlarged mouth, far away
+        let fits = |size: f32, bound: u32| (0. ..=2. * bound as f32).contains(&size);
+        if !fits(canvas_size.x, image.width()) || !fits(canvas_size.y, image.height()) {
+            return Err(Error::MathError(String::from("projection out of bounds")));
+        }
+        let canvas_size = Point::<u32>::from(&canvas_size);
         // preallocate ciya canvas
-        let mut warped_ciya = RgbaImage::new(
-            scaled_size.x * antialias_scale,
-            scaled_size.y * antialias_scale,
-        );
-
-        imageproc::geometric_transformations::warp_into(
-            ciya,
-            &projection,
-            Interpolation::Bicubic,
-            Rgba([0, 0, 0, 0]),
-            &mut warped_ciya,
-        );
-
-        // downscale to target size
-        let warped_ciya = imageops::resize(
-            &warped_ciya,
-            scaled_size.x,
-            scaled_size.y,
-            FilterType::Lanczos3,
-        );
-        imageops::overlay(&mut image, &warped_ciya, offset.x as i64, offset.y as i64);
+        let mut warped_ciya = Canvas::new(canvas_size.x, canvas_size.y, self.blending);
+
+        // antialias_scale bounds the samples taken per axis of each pixel
+        debug_span!(
+            "warp",
+            width = warped_ciya.width,
+            height = warped_ciya.height
+        )
+        .in_scope(|| warp_into(ciya, &projection, antialias_scale, &mut warped_ciya));
+        debug_span!("overlay", x = offset.x, y = offset.y).in_scope(|| {
+            overlay(&mut image, &warped_ciya, offset.x as i64, offset.y as i64);
+        });
         Ok(image)
     }
 
@@ -143,14 +183,17 @@ impl Projector {
                     self.ciya_image.width() as f32,
                     self.ciya_image.height() as f32,
                 )),
-                control_points.centralize_y().enlarge(0.3, true),
+                control_points.centralize_y()?.enlarge(0.3, true)?,
             ),
             ProjectionStrategy::RespectEdge { smile } => {
-                let target_ctrl_pts = control_points.enlarge(0.3, false);
+                let target_ctrl_pts = control_points.enlarge(0.3, false)?;
                 let ciya_ctrl_pts = {
-                    let y0 = target_ctrl_pts.cross().y;
+                    let y0 = target_ctrl_pts.cross()?.y;
                     let factor =
                         (y0 - target_ctrl_pts.p2.y) / (target_ctrl_pts.p4.y - target_ctrl_pts.p2.y);
+                    if !factor.is_finite() {
+                        return None;
+                    }
                     let y = 200. * factor;
 
                     // \frac{(x-180)^2}{180^2} + \frac{y^2}{200^2} = 1
@@ -190,7 +233,7 @@ impl Projector {
 fn is_smile<T: Num + NumCast + PartialOrd + Copy>(
     control_points: &ControlPoints<T>,
 ) -> Option<bool> {
-    let Point { x: _, y: y0 } = control_points.cross();
+    let Point { x: _, y: y0 } = control_points.cross()?;
     Some(user_abs_minus(control_points.p2.y, y0)? <= user_abs_minus(control_points.p4.y, y0)?)
 }
 
@@ -239,17 +239,21 @@ impl<T: Num + NumCast + PartialOrd + Copy> ControlPoints<T> {
         Self { p1, p2, p3, p4 }
     }
 
-    pub fn cross(&self) -> Point<T> {
+    /// Intersection of the line through `p1` and `p3` with the one through
+    /// `p2` and `p4`, or `None` if they are parallel.
+    pub fn cross(&self) -> Option<Point<T>> {
         let [(x1, y1), (x3, y3), (x2, y2), (x4, y4)]: [(T, T); 4] = self.into();
         let x1_2 = x1 - x2;
         let x3_4 = x3 - x4;
         let y1_2 = y1 - y2;
         let y3_4 = y3 - y4;
-        let x0 =
-            (x3_4 * (x2 * y1 - x1 * y2) - x1_2 * (x4 * y3 - x3 * y4)) / (x3_4 * y1_2 - x1_2 * y3_4);
-        let y0 =
-            (y3_4 * (y2 * x1 - y1 * x2) - y1_2 * (y4 * x3 - y3 * x4)) / (y3_4 * x1_2 - y1_2 * x3_4);
-        Point::new(x0, y0)
+        let denominator = x3_4 * y1_2 - x1_2 * y3_4;
+        if denominator.is_zero() {
+            return None;
+        }
+        let x0 = (x3_4 * (x2 * y1 - x1 * y2) - x1_2 * (x4 * y3 - x3 * y4)) / denominator;
+        let y0 = (y1_2 * (y4 * x3 - y3 * x4) - y3_4 * (y2 * x1 - y1 * x2)) / denominator;
+        Some(Point::new(x0, y0))
     }
 
     pub fn center(&self) -> Point<T> {
@@ -259,36 +263,36 @@ impl<T: Num + NumCast + PartialOrd + Copy> ControlPoints<T> {
         Point::new(x_0, y_0)
     }
 
-    pub fn enlarge(&self, scale: T, use_center: bool) -> Self {
+    pub fn enlarge(&self, scale: T, use_center: bool) -> Option<Self> {
         let origin = if use_center {
             self.center()
         } else {
-            self.cross()
+            self.cross()?
         };
 
-        *self + (*self) * scale - origin * scale
+        Some(*self + (*self) * scale - origin * scale)
     }
 
-    pub fn centralize_y(&self) -> Self {
-        let cross = self.cross();
+    pub fn centralize_y(&self) -> Option<Self> {
+        let cross = self.cross()?;
         let center = self.center();
 
-        Self {
+        Some(Self {
             p1: center - cross + self.p1,
             p2: self.p2,
             p3: center - cross + self.p3,
             p4: self.p4,
-        }
+        })
     }
 
     #[allow(clippy::match_like_matches_macro)]
     pub fn is_convex(&self) -> Option<bool> {
-        let Point { x: _, y: y0 } = self.cross();
+        let Point { x: _, y: y0 } = self.cross()?;
         Some(y0.partial_cmp(&self.p2.y)?.is_gt() || y0.partial_cmp(&self.p4.y)?.is_lt())
     }
 
     pub fn shift_origin(&self) -> Option<(Point<T>, Point<T>, Self)> {
-        let cross = self.cross();
+        let cross = self.cross()?;
 
         let px = self.p1 - cross + self.p2;
         let py = self.p3 - cross + self.p2;
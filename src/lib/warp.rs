@@ -0,0 +1,265 @@
+#[cfg(test)]
+use image::Rgba;
+use image::RgbaImage;
+use imageproc::geometric_transformations::Projection;
+use rayon::prelude::*;
+
+#[cfg(test)]
+use crate::color::linear_to_srgb;
+use crate::{color::srgb_to_linear, projector::Blending};
+
+/// Rows of the target image handled by a task at a time.
+pub(crate) const ROWS_PER_CHUNK: usize = 16;
+
+/// A warped image, in the color space of its `Blending`.
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    /// Straight sRGB channels, or premultiplied linear ones, spread over the
+    /// range of `u16` to keep the canvas small.
+    pub pixels: Vec<[u16; 4]>,
+    pub blending: Blending,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, blending: Blending) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0; 4]; width as usize * height as usize],
+            blending,
+        }
+    }
+
+    // The stored value of a channel at 1.
+    fn scale(blending: Blending) -> f32 {
+        match blending {
+            Blending::Srgb => 65535. / 255.,
+            Blending::Linear => 65535.,
+        }
+    }
+
+    /// Store a pixel of straight sRGB channels in `0..=255`, or premultiplied
+    /// linear ones in `0..=1`.
+    pub fn encode(blending: Blending, pixel: [f32; 4]) -> [u16; 4] {
+        let scale = Self::scale(blending);
+        pixel.map(|c| (c * scale).round().clamp(0., 65535.) as u16)
+    }
+
+    /// The pixel at `(x, y)`, in the channels it was encoded from.
+    pub fn get(&self, x: u32, y: u32) -> [f32; 4] {
+        let scale = Self::scale(self.blending);
+        self.pixels[y as usize * self.width as usize + x as usize].map(|c| f32::from(c) / scale)
+    }
+}
+
+// Only the tests need the canvas in sRGB, since it's composited right away.
+#[cfg(test)]
+impl Canvas {
+    /// The straight sRGB color of the pixel at `(x, y)`.
+    pub fn get_rgba(&self, x: u32, y: u32) -> Rgba<u8> {
+        let pixel = self.get(x, y);
+        match self.blending {
+            Blending::Srgb => Rgba(pixel.map(|c| c.round().clamp(0., 255.) as u8)),
+            Blending::Linear => {
+                let [r, g, b, alpha] = pixel;
+                if alpha <= 0. {
+                    return Rgba([0; 4]);
+                }
+                Rgba([
+                    linear_to_srgb(r / alpha),
+                    linear_to_srgb(g / alpha),
+                    linear_to_srgb(b / alpha),
+                    (alpha * 255.).round().clamp(0., 255.) as u8,
+                ])
+            }
+        }
+    }
+
+    pub fn to_rgba8(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| self.get_rgba(x, y))
+    }
+}
+
+/// Warp `image` by `projection` into `target`, antialiasing at the resolution
+/// of `target`. Chunks of rows are warped in parallel.
+///
+/// Each target pixel is mapped back onto `image`, and sampled at a grid of
+/// points spread over its footprint there. The grid is as dense as the
+/// footprint is large, up to `max_samples` points per axis.
+pub fn warp_into(
+    image: &RgbaImage,
+    projection: &Projection,
+    max_samples: u32,
+    target: &mut Canvas,
+) {
+    let inverse = projection.invert();
+    let (width, blending) = (target.width as usize, target.blending);
+    if width == 0 {
+        return;
+    }
+    target
+        .pixels
+        .par_chunks_mut(width * ROWS_PER_CHUNK)
+        .enumerate()
+        .for_each(|(chunk, rows)| {
+            for (i, row) in rows.chunks_mut(width).enumerate() {
+                let y = (chunk * ROWS_PER_CHUNK + i) as f32;
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = Canvas::encode(
+                        blending,
+                        warp_pixel(image, inverse, max_samples, blending, x as f32, y),
+                    );
+                }
+            }
+        });
+}
+
+fn warp_pixel(
+    image: &RgbaImage,
+    inverse: Projection,
+    max_samples: u32,
+    blending: Blending,
+    x: f32,
+    y: f32,
+) -> [f32; 4] {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let corners = [
+        inverse * (x, y),
+        inverse * (x + 1., y),
+        inverse * (x, y + 1.),
+        inverse * (x + 1., y + 1.),
+    ];
+    let (mut left, mut top, mut right, mut bottom) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for (u, v) in corners {
+        left = left.min(u);
+        top = top.min(v);
+        right = right.max(u);
+        bottom = bottom.max(v);
+    }
+    // also rejects NaN
+    if !(right > 0. && bottom > 0. && left < width && top < height) {
+        return [0.; 4];
+    }
+
+    let extent = (right - left).max(bottom - top);
+    let samples = (extent.ceil() as u32).clamp(1, max_samples.max(1));
+    let step = 1. / samples as f32;
+    let mut sum = [0f32; 4];
+    for j in 0..samples {
+        for i in 0..samples {
+            let (u, v) = inverse * (x + (i as f32 + 0.5) * step, y + (j as f32 + 0.5) * step);
+            for (sum, channel) in sum.iter_mut().zip(sample_bilinear(image, blending, u, v)) {
+                *sum += channel;
+            }
+        }
+    }
+    let count = (samples * samples) as f32;
+    sum.map(|channel| channel / count)
+}
+
+// Sample `image` at `(u, v)`, where pixel centers lie at half-integers and
+// everything outside is transparent.
+fn sample_bilinear(image: &RgbaImage, blending: Blending, u: f32, v: f32) -> [f32; 4] {
+    let (u, v) = (u - 0.5, v - 0.5);
+    let (x0, y0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - x0, v - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let texel = |x: i64, y: i64| -> [f32; 4] {
+        if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+            return [0.; 4];
+        }
+        let pixel = image.get_pixel(x as u32, y as u32).0;
+        match blending {
+            Blending::Srgb => pixel.map(f32::from),
+            Blending::Linear => {
+                let alpha = f32::from(pixel[3]) / 255.;
+                [
+                    srgb_to_linear(pixel[0]) * alpha,
+                    srgb_to_linear(pixel[1]) * alpha,
+                    srgb_to_linear(pixel[2]) * alpha,
+                    alpha,
+                ]
+            }
+        }
+    };
+    let (p00, p10, p01, p11) = (
+        texel(x0, y0),
+        texel(x0 + 1, y0),
+        texel(x0, y0 + 1),
+        texel(x0 + 1, y0 + 1),
+    );
+    let mut result = [0.; 4];
+    for (c, result) in result.iter_mut().enumerate() {
+        let top = p00[c] + (p10[c] - p00[c]) * fx;
+        let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+        *result = top + (bottom - top) * fy;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use imageproc::geometric_transformations::Projection;
+
+    use super::{warp_into, Canvas};
+    use crate::projector::Blending;
+
+    // an opaque image where every pixel differs from its neighbours
+    fn source() -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |x, y| {
+            Rgba([x as u8 * 32, y as u8 * 32, (x + y) as u8 * 16, 255])
+        })
+    }
+
+    fn warp(projection: &Projection, width: u32, height: u32, blending: Blending) -> RgbaImage {
+        let mut canvas = Canvas::new(width, height, blending);
+        warp_into(&source(), projection, 4, &mut canvas);
+        canvas.to_rgba8()
+    }
+
+    #[test]
+    fn identity_reproduces_source() {
+        let identity = Projection::scale(1., 1.);
+        for blending in [Blending::Srgb, Blending::Linear] {
+            assert_eq!(warp(&identity, 8, 8, blending), source());
+        }
+    }
+
+    #[test]
+    fn downscale_averages_blocks() {
+        let (source, warped) = (
+            source(),
+            warp(&Projection::scale(0.5, 0.5), 4, 4, Blending::Srgb),
+        );
+        for (x, y, pixel) in warped.enumerate_pixels() {
+            for c in 0..4 {
+                let block = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|(dx, dy)| u32::from(source.get_pixel(x * 2 + dx, y * 2 + dy)[c]))
+                    .sum::<u32>();
+                let average = (block as f32 / 4.).round() as u8;
+                assert!(
+                    pixel[c].abs_diff(average) <= 1,
+                    "channel {} of ({}, {}) is {}, not {}",
+                    c,
+                    x,
+                    y,
+                    pixel[c],
+                    average
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn outside_footprint_is_transparent() {
+        let warped = warp(&Projection::translate(4., 4.), 16, 16, Blending::Linear);
+        for (x, y, pixel) in warped.enumerate_pixels() {
+            let inside = (4..12).contains(&x) && (4..12).contains(&y);
+            assert_eq!(pixel[3], if inside { 255 } else { 0 }, "at ({}, {})", x, y);
+        }
+    }
+}
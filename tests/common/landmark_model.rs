@@ -0,0 +1,124 @@
+// A tiny stand-in for the landmark model, so that the real detector runs
+// offline. It takes the same input as the real one, and puts the peaks of the
+// mouth heatmaps at `MOUTH`, whatever the face.
+
+/// Where the mouth heatmaps peak, in the 128×128 face. Left, top, right and
+/// bottom, like `ControlPoints`.
+pub const MOUTH: [(u32, u32); 4] = [(40, 88), (64, 80), (88, 88), (64, 100)];
+
+// Heatmaps the real model outputs before the mouth ones.
+const SKIPPED: usize = 20;
+const SIZE: u64 = 128;
+
+// Just enough of protobuf to encode the messages of an ONNX model.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(mut self, field: u32, value: u64) -> Self {
+        self.key(field, 0);
+        self.raw_varint(value);
+        self
+    }
+
+    fn bytes(mut self, field: u32, bytes: &[u8]) -> Self {
+        self.key(field, 2);
+        self.raw_varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn string(self, field: u32, string: &str) -> Self {
+        self.bytes(field, string.as_bytes())
+    }
+
+    fn message(self, field: u32, message: Self) -> Self {
+        self.bytes(field, &message.0)
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.raw_varint(u64::from(field << 3 | u32::from(wire_type)));
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+const FLOAT: u64 = 1;
+
+fn tensor(name: &str, dims: &[u64], values: &[f32]) -> Message {
+    let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    dims.iter()
+        .fold(Message::default(), |tensor, dim| tensor.varint(1, *dim))
+        .varint(2, FLOAT)
+        .string(8, name)
+        .bytes(9, &raw)
+}
+
+fn value_info(name: &str, dims: &[u64]) -> Message {
+    let shape = dims.iter().fold(Message::default(), |shape, dim| {
+        shape.message(1, Message::default().varint(1, *dim))
+    });
+    let tensor_type = Message::default().varint(1, FLOAT).message(2, shape);
+    Message::default()
+        .string(1, name)
+        .message(2, Message::default().message(1, tensor_type))
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str) -> Message {
+    inputs
+        .iter()
+        .fold(Message::default(), |node, input| node.string(1, input))
+        .string(2, output)
+        .string(3, output)
+        .string(4, op_type)
+}
+
+/// The model, serialized as ONNX.
+///
+/// Heatmap `c` is `-((x - cx[c])² + (y - cy[c])²)`, which peaks at
+/// `(cx[c], cy[c])`. The input is multiplied by zero and added, so that it's
+/// used.
+pub fn landmark_model() -> Vec<u8> {
+    let channels = SKIPPED + MOUTH.len();
+    let (mut cx, mut cy) = (vec![0.; channels], vec![0.; channels]);
+    for (i, (x, y)) in MOUTH.iter().enumerate() {
+        cx[SKIPPED + i] = *x as f32;
+        cy[SKIPPED + i] = *y as f32;
+    }
+    let range: Vec<_> = (0..SIZE).map(|i| i as f32).collect();
+    let channels = channels as u64;
+
+    let graph = vec![
+        node("ReduceMax", &["input"], "input_max"),
+        node("Mul", &["input_max", "zero"], "nothing"),
+        node("Sub", &["xs", "cx"], "dx"),
+        node("Sub", &["ys", "cy"], "dy"),
+        node("Mul", &["dx", "dx"], "dx2"),
+        node("Mul", &["dy", "dy"], "dy2"),
+        node("Add", &["dx2", "dy2"], "distance"),
+        node("Sub", &["nothing", "distance"], "heatmaps"),
+    ]
+    .into_iter()
+    .fold(Message::default(), |graph, node| graph.message(1, node))
+    .string(2, "landmark_stand_in")
+    .message(5, tensor("zero", &[1], &[0.]))
+    .message(5, tensor("xs", &[1, 1, 1, SIZE], &range))
+    .message(5, tensor("ys", &[1, 1, SIZE, 1], &range))
+    .message(5, tensor("cx", &[1, channels, 1, 1], &cx))
+    .message(5, tensor("cy", &[1, channels, 1, 1], &cy))
+    .message(11, value_info("input", &[1, 3, SIZE, SIZE]))
+    .message(12, value_info("heatmaps", &[1, channels, SIZE, SIZE]));
+
+    Message::default()
+        .varint(1, 7)
+        .string(2, "ciya-rs tests")
+        .message(7, graph)
+        .message(8, Message::default().string(1, "").varint(2, 13))
+        .0
+}
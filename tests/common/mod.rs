@@ -0,0 +1,115 @@
+// Helpers shared by integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use image::DynamicImage;
+
+pub mod landmark_model;
+
+/// Set to record the current output as golden images instead of comparing
+/// against them.
+pub const UPDATE_GOLDEN_ENV: &str = "CIYA_UPDATE_GOLDEN";
+
+/// How far an image may stray from a golden one.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// A pixel differs if any of its channels is off by more than this.
+    pub channel: u8,
+    /// Share of pixels which may differ.
+    pub differing_pixels: f64,
+}
+
+// Absorbs rounding differences between platforms, and e.g. along the edges of
+// the warped ciya.
+const GOLDEN_TOLERANCE: Tolerance = Tolerance {
+    channel: 8,
+    differing_pixels: 0.002,
+};
+
+pub fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name))
+}
+
+/// Compare `image` against the golden image `name`. If `update` is set,
+/// `image` is recorded as the golden one instead.
+///
+/// On mismatch, `image` is saved to the test temp dir for inspection.
+pub fn check_golden(name: &str, image: &DynamicImage, update: bool) -> Result<(), String> {
+    let path = golden_path(name);
+    if update {
+        std::fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+        image.save(&path).map_err(|e| e.to_string())?;
+        eprintln!("recorded golden image {}", path.display());
+        return Ok(());
+    }
+    if !path.is_file() {
+        return Err(format!(
+            "missing golden image {}, set {} to record it",
+            path.display(),
+            UPDATE_GOLDEN_ENV
+        ));
+    }
+    check_similar(name, image, GOLDEN_TOLERANCE)
+        .map_err(|e| format!("{}, set {} to accept it", e, UPDATE_GOLDEN_ENV))
+}
+
+/// Compare `image` against the golden image `name` within `tolerance`, without
+/// ever recording it.
+///
+/// On mismatch, `image` is saved to the test temp dir for inspection.
+pub fn check_similar(name: &str, image: &DynamicImage, tolerance: Tolerance) -> Result<(), String> {
+    let path = golden_path(name);
+    let golden = image::open(&path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?
+        .into_rgba8();
+    let actual = image.to_rgba8();
+    let mismatch = if golden.dimensions() == actual.dimensions() {
+        let differing = golden
+            .pixels()
+            .zip(actual.pixels())
+            .filter(|(a, b)| {
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .any(|(a, b)| a.abs_diff(*b) > tolerance.channel)
+            })
+            .count();
+        let share = differing as f64 / f64::from(golden.width() * golden.height());
+        (share > tolerance.differing_pixels)
+            .then(|| format!("{} pixels ({:.2}%) differ", differing, share * 100.))
+    } else {
+        Some(format!(
+            "size {:?} differs from {:?}",
+            actual.dimensions(),
+            golden.dimensions()
+        ))
+    };
+
+    match mismatch {
+        None => Ok(()),
+        Some(mismatch) => {
+            let actual_path = Path::new(env!("CARGO_TARGET_TMPDIR"))
+                .join(format!("{}.png", name.replace('/', "_")));
+            let _ = image.save(&actual_path);
+            Err(format!(
+                "{} doesn't match {}: {}. Output saved to {}",
+                name,
+                path.display(),
+                mismatch,
+                actual_path.display()
+            ))
+        }
+    }
+}
+
+pub fn assert_golden(name: &str, image: &DynamicImage) {
+    if let Err(e) = check_golden(name, image, env::var_os(UPDATE_GOLDEN_ENV).is_some()) {
+        panic!("{}", e);
+    }
+}
@@ -0,0 +1,146 @@
+use ciya_lib::{
+    ciyafier::{ControlPoints, Emotion, Point},
+    errors::Error,
+    projector::Projector,
+};
+use image::{DynamicImage, RgbaImage};
+use lazy_static::lazy_static;
+use proptest::prelude::*;
+
+lazy_static! {
+    static ref PROJECTOR: Projector = Projector::new();
+}
+
+const EPSILON: f32 = 1e-3;
+
+fn close(a: Point<f32>, b: Point<f32>) -> bool {
+    let scale = 1f32.max(a.x.abs()).max(a.y.abs());
+    (a.x - b.x).abs() <= EPSILON * scale && (a.y - b.y).abs() <= EPSILON * scale
+}
+
+// A mouth whose axes cross at `cross`. The corners lie on a line of slope
+// `tilt`, the top and bottom on a line whose x changes by `lean` per y.
+prop_compose! {
+    fn convex_mouth()(
+        cx in 16f32..240., cy in 16f32..240.,
+        left in 1f32..64., right in 1f32..64., top in 1f32..64., bottom in 1f32..64.,
+        tilt in -0.5f32..0.5, lean in -0.5f32..0.5,
+    ) -> (ControlPoints<f32>, Point<f32>) {
+        let cross = Point::new(cx, cy);
+        let corner = Point::new(1., tilt);
+        let axis = Point::new(lean, 1.);
+        (
+            ControlPoints::new(
+                cross - corner * left,
+                cross - axis * top,
+                cross + corner * right,
+                cross + axis * bottom,
+            ),
+            cross,
+        )
+    }
+}
+
+fn any_point() -> impl Strategy<Value = Point<f32>> {
+    (-256f32..256., -256f32..256.).prop_map(|(x, y)| Point::new(x, y))
+}
+
+fn any_mouth() -> impl Strategy<Value = ControlPoints<f32>> {
+    (any_point(), any_point(), any_point(), any_point())
+        .prop_map(|(p1, p2, p3, p4)| ControlPoints::new(p1, p2, p3, p4))
+}
+
+proptest! {
+    #[test]
+    fn cross_is_where_axes_meet((mouth, cross) in convex_mouth()) {
+        prop_assert!(close(mouth.cross().unwrap(), cross));
+    }
+
+    #[test]
+    fn cross_lies_inside_convex_mouth((mouth, _) in convex_mouth()) {
+        let cross = mouth.cross().unwrap();
+        let [p1, p2, p3, p4] = [mouth.p1, mouth.p2, mouth.p3, mouth.p4];
+        let (x_min, x_max) = (p1.x.min(p2.x).min(p3.x).min(p4.x), p1.x.max(p2.x).max(p3.x).max(p4.x));
+        let (y_min, y_max) = (p1.y.min(p2.y).min(p3.y).min(p4.y), p1.y.max(p2.y).max(p3.y).max(p4.y));
+        prop_assert!((x_min..=x_max).contains(&cross.x));
+        prop_assert!((y_min..=y_max).contains(&cross.y));
+        prop_assert_eq!(mouth.is_convex(), Some(true));
+    }
+
+    #[test]
+    fn enlarge_preserves_cross((mouth, cross) in convex_mouth(), scale in 0f32..2.) {
+        let enlarged = mouth.enlarge(scale, false).unwrap();
+        prop_assert!(close(enlarged.cross().unwrap(), cross));
+    }
+
+    #[test]
+    fn enlarge_preserves_center((mouth, _) in convex_mouth(), scale in 0f32..2.) {
+        let enlarged = mouth.enlarge(scale, true).unwrap();
+        prop_assert!(close(enlarged.center(), mouth.center()));
+    }
+
+    #[test]
+    fn centralize_y_keeps_mouth_regular((mouth, _) in convex_mouth()) {
+        prop_assert!(!mouth.centralize_y().unwrap().is_irregular());
+    }
+
+    #[test]
+    fn shift_origin_bounds_mouth((mouth, _) in convex_mouth()) {
+        let (left_top, right_bottom, shifted) = mouth.shift_origin().unwrap();
+        let size = right_bottom - left_top;
+        for p in [shifted.p1, shifted.p2, shifted.p3, shifted.p4] {
+            prop_assert!((0. ..=size.x).contains(&p.x));
+            prop_assert!((0. ..=size.y).contains(&p.y));
+        }
+    }
+
+    // integral coordinates keep the arithmetic exact
+    #[test]
+    fn parallel_axes_have_no_cross(
+        (px, py) in (-256i16..256, -256i16..256),
+        (dx, dy) in (-16i16..16, -16i16..16),
+        a in 1i16..16,
+        b in 1i16..16,
+    ) {
+        prop_assume!(dx != 0 || dy != 0);
+        let p = Point::new(f32::from(px), f32::from(py));
+        let d = Point::new(f32::from(dx), f32::from(dy));
+        let offset = Point::new(-d.y, d.x);
+        let mouth = ControlPoints::new(
+            p,
+            p + offset,
+            p + d * f32::from(a),
+            p + offset + d * f32::from(b),
+        );
+        prop_assert!(mouth.cross().is_none());
+        prop_assert!(mouth.enlarge(0.3, false).is_none());
+        prop_assert!(mouth.centralize_y().is_none());
+        prop_assert!(mouth.shift_origin().is_none());
+    }
+
+    // a mouth turned on its side, whose top and bottom are level
+    #[test]
+    fn level_axis_is_an_error(
+        (cx, cy) in (16i16..112, 16i16..112),
+        (left, right) in (1i16..16, 1i16..16),
+        (top, bottom) in (1i16..16, 1i16..16),
+        antialias_scale in 1u32..4,
+    ) {
+        let (cx, cy) = (f32::from(cx), f32::from(cy));
+        let mouth = ControlPoints::new(
+            Point::new(cx, cy - f32::from(left)),
+            Point::new(cx - f32::from(top), cy),
+            Point::new(cx, cy + f32::from(right)),
+            Point::new(cx + f32::from(bottom), cy),
+        );
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(128, 128));
+        let result = PROJECTOR.project(image, mouth, Emotion::Auto, antialias_scale);
+        prop_assert!(matches!(result, Err(Error::MathError(_))));
+    }
+
+    #[test]
+    fn projection_never_panics(mouth in any_mouth(), antialias_scale in 0u32..4) {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(128, 128));
+        let _ = PROJECTOR.project(image, mouth, Emotion::Auto, antialias_scale);
+    }
+}
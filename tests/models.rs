@@ -0,0 +1,361 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use ciya_lib::{
+    errors::Error,
+    models::{Model, ModelManager, Progress},
+};
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use tempfile::tempdir;
+
+const SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone)]
+struct Request {
+    path: String,
+    range: Option<u64>,
+}
+
+enum Reply {
+    Status(u16),
+    Full,
+    // the content from an offset on, as a partial response
+    From(u64),
+    // the headers of the full content, but only its first bytes
+    Truncated(usize),
+    // nothing is left after the requested offset
+    Unsatisfiable,
+}
+
+type Requests = Arc<Mutex<Vec<Request>>>;
+
+fn content() -> Vec<u8> {
+    (0..SIZE).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn sha256(content: &[u8]) -> &'static str {
+    Box::leak(format!("{:x}", Sha256::digest(content)).into_boxed_str())
+}
+
+fn model(url: &str, sha256: Option<&'static str>) -> Model {
+    Model {
+        name: "test",
+        version: "1",
+        file_name: "test.bin",
+        url: Box::leak(format!("{}/upstream/test.bin", url).into_boxed_str()),
+        sha256,
+    }
+}
+
+fn manager(root: &std::path::Path) -> ModelManager {
+    ModelManager::new(root)
+        .timeout(Duration::from_secs(10))
+        .retries(3, Duration::from_millis(10))
+}
+
+#[test]
+fn retries_transient_failures() {
+    let (url, requests) = stand_in(|i, _| {
+        if i == 0 {
+            Reply::Status(503)
+        } else {
+            Reply::Full
+        }
+    });
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+
+    let path = manager(dir.path()).fetch(&model).unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn resumes_interrupted_downloads() {
+    let (url, requests) = stand_in(|i, request| match (i, request.range) {
+        (0, _) => Reply::Truncated(SIZE / 2),
+        (_, Some(offset)) => Reply::From(offset),
+        (_, None) => Reply::Full,
+    });
+    let dir = tempdir().unwrap();
+    // pinned on first download
+    let model = model(&url, None);
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let manager = {
+        let progress = Arc::clone(&progress);
+        manager(dir.path()).on_progress(move |_, p| progress.lock().unwrap().push(p))
+    };
+
+    let path = manager.fetch(&model).unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    assert_eq!(requests.lock().unwrap()[1].range, Some(SIZE as u64 / 2));
+    manager.verify(&model).unwrap();
+    assert_eq!(
+        progress.lock().unwrap().last(),
+        Some(&Progress {
+            downloaded: SIZE as u64,
+            total: Some(SIZE as u64)
+        })
+    );
+}
+
+#[test]
+fn finishes_complete_partial_download() {
+    let (url, requests) = stand_in(|_, request| match request.range {
+        Some(_) => Reply::Unsatisfiable,
+        None => Reply::Full,
+    });
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+    let manager = manager(dir.path());
+    // as if a download was interrupted right before being renamed into place
+    let partial = manager.partial_path(&model, model.url);
+    fs::create_dir_all(partial.parent().unwrap()).unwrap();
+    fs::write(&partial, content()).unwrap();
+
+    let path = manager.fetch(&model).unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    assert!(!partial.exists());
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn restarts_when_range_is_ignored() {
+    let (url, requests) = stand_in(|i, request| match (i, request.range) {
+        (0, _) => Reply::Truncated(SIZE / 2),
+        // from the beginning, although the rest was asked for
+        (_, Some(_)) => Reply::From(0),
+        (_, None) => Reply::Full,
+    });
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+
+    let path = manager(dir.path()).fetch(&model).unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    let ranges: Vec<_> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request.range)
+        .collect();
+    assert_eq!(ranges, [None, Some(SIZE as u64 / 2), None]);
+}
+
+#[test]
+fn waits_for_concurrent_download() {
+    let (url, requests) = stand_in(|_, _| Reply::Full);
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+    let path = manager(dir.path()).path(&model);
+    // as if another process was downloading it
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let other = File::create(path.with_file_name("test.bin.lock")).unwrap();
+    other.lock_exclusive().unwrap();
+
+    let waiting = {
+        let root = dir.path().to_path_buf();
+        thread::spawn(move || manager(&root).ensure(&model))
+    };
+    thread::sleep(Duration::from_millis(200));
+    assert!(!waiting.is_finished());
+    fs::write(&path, content()).unwrap();
+    other.unlock().unwrap();
+
+    assert_eq!(waiting.join().unwrap().unwrap(), path);
+    // the other download is used instead of starting another one
+    assert!(requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn drops_partial_download_when_switching_mirrors() {
+    let (url, requests) =
+        stand_in(
+            |_, request| match (request.path.starts_with("/mirror/"), request.range) {
+                (true, None) => Reply::Truncated(SIZE / 2),
+                (true, Some(_)) => Reply::Status(404),
+                (false, _) => Reply::Full,
+            },
+        );
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+    let mirror = format!("{}/mirror", url);
+
+    let manager = manager(dir.path()).mirrors([mirror.clone()]);
+    let path = manager.fetch(&model).unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    assert!(!manager
+        .partial_path(&model, &format!("{}/test/1/test.bin", mirror))
+        .exists());
+    let requests = requests.lock().unwrap();
+    let last = requests.last().unwrap();
+    assert_eq!(
+        (last.path.as_str(), last.range),
+        ("/upstream/test.bin", None)
+    );
+}
+
+#[test]
+fn falls_back_to_upstream_after_mirrors() {
+    let (url, requests) = stand_in(|_, request| {
+        if request.path.starts_with("/mirror/") {
+            Reply::Status(404)
+        } else {
+            Reply::Full
+        }
+    });
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(&content())));
+
+    let path = manager(dir.path())
+        .mirrors([format!("{}/mirror/", url)])
+        .fetch(&model)
+        .unwrap();
+    assert_eq!(fs::read(path).unwrap(), content());
+    // client errors aren't retried
+    let paths: Vec<_> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    assert_eq!(paths, ["/mirror/test/1/test.bin", "/upstream/test.bin"]);
+}
+
+#[test]
+fn pins_first_download_from_upstream() {
+    let (url, requests) = stand_in(|_, _| Reply::Full);
+    let dir = tempdir().unwrap();
+    let model = model(&url, None);
+    let manager = manager(dir.path()).mirrors([format!("{}/mirror", url)]);
+    let path = manager.fetch(&model).unwrap();
+    let pin = path.with_file_name("test.bin.sha256");
+    assert_eq!(fs::read_to_string(&pin).unwrap(), sha256(&content()));
+    // a mirror could serve anything, which would be pinned for good
+    let paths: Vec<_> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    assert_eq!(paths, ["/upstream/test.bin"]);
+
+    // as if the model was pinned to another file, which the server no longer
+    // serves
+    fs::write(&pin, sha256(b"something else")).unwrap();
+    assert!(matches!(
+        manager.fetch(&model),
+        Err(Error::ChecksumMismatch(_))
+    ));
+    assert_eq!(fs::read_to_string(&pin).unwrap(), sha256(b"something else"));
+}
+
+#[test]
+fn rejects_checksum_mismatch() {
+    let (url, _) = stand_in(|_, _| Reply::Full);
+    let dir = tempdir().unwrap();
+    let model = model(&url, Some(sha256(b"something else")));
+
+    let manager = manager(dir.path());
+    assert!(matches!(
+        manager.fetch(&model),
+        Err(Error::ChecksumMismatch(_))
+    ));
+    assert!(!manager.path(&model).exists());
+}
+
+// Start a stand-in HTTP server serving `content()` as told by `reply`, which
+// is given the index and the request. Returns its url and the requests it
+// received.
+fn stand_in<F>(reply: F) -> (String, Requests)
+where
+    F: Fn(usize, &Request) -> Reply + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Requests::default();
+    {
+        let requests = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let request = read_request(&stream);
+                let i = {
+                    let mut requests = requests.lock().unwrap();
+                    requests.push(request.clone());
+                    requests.len() - 1
+                };
+                let _ = respond(stream, reply(i, &request));
+            }
+        });
+    }
+    (url, requests)
+}
+
+fn read_request(stream: &TcpStream) -> Request {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut range = None;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = value
+                    .trim()
+                    .strip_prefix("bytes=")
+                    .and_then(|value| value.strip_suffix('-'))
+                    .map(|offset| offset.parse().unwrap());
+            }
+        }
+    }
+    Request { path, range }
+}
+
+fn respond(mut stream: TcpStream, reply: Reply) -> std::io::Result<()> {
+    let content = content();
+    let (status, headers, body) = match reply {
+        Reply::Status(status) => (status, String::new(), &[][..]),
+        Reply::Full => (200, String::new(), &content[..]),
+        Reply::From(offset) => (
+            206,
+            format!("Content-Range: bytes {}-{}/{}\r\n", offset, SIZE - 1, SIZE),
+            &content[offset as usize..],
+        ),
+        Reply::Unsatisfiable => (416, format!("Content-Range: bytes */{}\r\n", SIZE), &[][..]),
+        Reply::Truncated(len) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                SIZE
+            )?;
+            stream.write_all(&content[..len])?;
+            return stream.flush();
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} Stand-in\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        headers,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
@@ -0,0 +1,144 @@
+use std::{fs, io::Cursor};
+
+use ciya_lib::{
+    ciyafier::{Ciyafier, ControlPoints, Emotion, Point},
+    codec,
+    detectors::{MouthDetectorTrait, WeebDetector},
+    errors::{Error, Result},
+};
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
+use tempfile::tempdir;
+
+use crate::common::landmark_model::landmark_model;
+
+mod common;
+
+const TEST_IMAGE: &[u8] = include_bytes!("test.png");
+const FACE_MODEL: &[u8] = include_bytes!("../resources/lbpcascade_animeface.xml");
+
+// Where the mouth is in `test.png`.
+const MOUTH: ControlPoints<f32> = ControlPoints::new(
+    Point::new(190., 318.),
+    Point::new(212., 306.),
+    Point::new(236., 316.),
+    Point::new(213., 330.),
+);
+
+// Stands in for the whole detector, to check the projection on its own.
+struct StubDetector(Option<ControlPoints<f32>>);
+
+impl MouthDetectorTrait for StubDetector {
+    fn detect(&self, _image: &DynamicImage) -> Result<ControlPoints<f32>> {
+        self.0.ok_or(Error::NoneError)
+    }
+}
+
+fn test_image() -> DynamicImage {
+    ImageReader::with_format(Cursor::new(TEST_IMAGE), ImageFormat::Png)
+        .decode()
+        .unwrap()
+}
+
+#[test]
+fn ciya_matches_golden() {
+    let ciyafier = Ciyafier::new(Box::new(StubDetector(Some(MOUTH))));
+    for (emotion, name) in [
+        (Emotion::Auto, "ciya_auto"),
+        (Emotion::Smile, "ciya_smile"),
+        (Emotion::Cry, "ciya_cry"),
+    ] {
+        let image = ciyafier.ciya(test_image(), emotion, 4).unwrap();
+        assert_eq!((image.width(), image.height()), (512, 464));
+        common::assert_golden(name, &image);
+    }
+}
+
+#[test]
+fn missing_mouth_is_an_error() {
+    let ciyafier = Ciyafier::new(Box::new(StubDetector(None)));
+    assert!(matches!(
+        ciyafier.ciya(test_image(), Emotion::Auto, 4),
+        Err(Error::NoneError)
+    ));
+}
+
+#[test]
+fn corrupt_face_model_is_an_error() {
+    assert!(WeebDetector::from_bytes(b"not a cascade", b"not a model").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_model_path_is_an_error() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = tempdir().unwrap();
+    let face_model = dir.path().join(OsStr::from_bytes(b"face\xff.xml"));
+    fs::write(&face_model, FACE_MODEL).unwrap();
+    let landmark_model_path = dir.path().join("landmark.onnx");
+    fs::write(&landmark_model_path, landmark_model()).unwrap();
+    assert!(matches!(
+        WeebDetector::new(&face_model, &landmark_model_path),
+        Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+    ));
+}
+
+#[test]
+fn weeb_detector_runs_offline() {
+    let detector = WeebDetector::from_bytes(FACE_MODEL, &landmark_model()).unwrap();
+    let image = test_image();
+
+    // the landmarks of the stand-in model, rebased onto the face found by the
+    // real face model
+    let ControlPoints { p1, p2, p3, p4 } = detector.detect(&image).unwrap();
+    assert_eq!(p1.y, p3.y);
+    assert_eq!(p2.x, p4.x);
+    assert!(p1.x < p2.x && p2.x < p3.x);
+    assert!(p2.y < p1.y && p1.y < p4.y);
+    for point in [p1, p2, p3, p4] {
+        assert!((0. ..image.width() as f32).contains(&point.x));
+        assert!((0. ..image.height() as f32).contains(&point.y));
+    }
+
+    let ciyafier = Ciyafier::new(Box::new(detector));
+    let ciyaified = ciyafier.ciya(image, Emotion::Auto, 4).unwrap();
+    assert_eq!(
+        (ciyaified.width(), ciyaified.height()),
+        (test_image().width(), test_image().height())
+    );
+}
+
+#[test]
+fn weeb_detector_sources_agree() {
+    let landmark_model = landmark_model();
+    let dir = tempdir().unwrap();
+    let (face_path, landmark_path) = (
+        dir.path().join("face.xml"),
+        dir.path().join("landmark.onnx"),
+    );
+    fs::write(&face_path, FACE_MODEL).unwrap();
+    fs::write(&landmark_path, &landmark_model).unwrap();
+    let image = test_image();
+
+    let detectors = [
+        WeebDetector::new(&face_path, &landmark_path).unwrap(),
+        WeebDetector::from_bytes(FACE_MODEL, &landmark_model).unwrap(),
+        WeebDetector::from_readers(FACE_MODEL, landmark_model.as_slice()).unwrap(),
+    ];
+    let mouths: Vec<_> = detectors
+        .iter()
+        .map(|detector| format!("{:?}", detector.detect(&image).unwrap()))
+        .collect();
+    assert!(
+        mouths.iter().all(|mouth| *mouth == mouths[0]),
+        "{:?}",
+        mouths
+    );
+}
+
+#[test]
+fn dimensions_come_from_header() {
+    assert_eq!(codec::dimensions(TEST_IMAGE).unwrap(), (512, 464));
+    // the pixels aren't needed
+    assert_eq!(codec::dimensions(&TEST_IMAGE[..64]).unwrap(), (512, 464));
+}
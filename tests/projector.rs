@@ -0,0 +1,240 @@
+//! Regression tests of the projector against golden images.
+//!
+//! Run `cargo test --test projector -- --update-golden` to record the current
+//! output as the golden images after an intended change.
+//!
+//! The output is also compared, more loosely, against `golden/warp_before`,
+//! which was recorded once from the warp this one replaced: supersampled at
+//! the antialias scale, downscaled with Lanczos and composited in sRGB. These
+//! are never updated. The runner takes
+//! enough of libtest's arguments to be listed and filtered by test runners,
+//! e.g. `--list`, `--exact` and `--skip`.
+
+use std::{env, process};
+
+use ciya_lib::{
+    ciyafier::{ControlPoints, Point},
+    errors::Error,
+    projector::{Blending, Emotion, Projector},
+};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::common::Tolerance;
+
+mod common;
+
+const ANTIALIAS_SCALE: u32 = 4;
+// The old warp resampled twice, which shows along the thin strokes of the ciya.
+const WARP_BEFORE_TOLERANCE: Tolerance = Tolerance {
+    channel: 24,
+    differing_pixels: 0.03,
+};
+
+struct Case {
+    name: &'static str,
+    control_points: [(f32, f32); 4],
+    emotion: Emotion,
+    blending: Blending,
+    // whether a golden image is expected, or a math error
+    valid: bool,
+}
+
+const fn case(
+    name: &'static str,
+    control_points: [(f32, f32); 4],
+    emotion: Emotion,
+    valid: bool,
+) -> Case {
+    Case {
+        name,
+        control_points,
+        emotion,
+        blending: Blending::Linear,
+        valid,
+    }
+}
+
+// Control points are left, top, right and bottom of the mouth.
+const CASES: &[Case] = &[
+    // the line between the corners is closer to the top, so it's a smile
+    case(
+        "convex",
+        [(40., 60.), (64., 50.), (88., 60.), (64., 80.)],
+        Emotion::Auto,
+        true,
+    ),
+    case(
+        "convex_flip",
+        [(40., 60.), (64., 50.), (88., 60.), (64., 80.)],
+        Emotion::Flip,
+        true,
+    ),
+    // to compare against blending in sRGB
+    Case {
+        name: "convex_srgb",
+        control_points: [(40., 60.), (64., 50.), (88., 60.), (64., 80.)],
+        emotion: Emotion::Auto,
+        blending: Blending::Srgb,
+        valid: true,
+    },
+    case(
+        "convex_tilted",
+        [(36., 70.), (60., 48.), (92., 56.), (68., 84.)],
+        Emotion::Auto,
+        true,
+    ),
+    // the top is below the bottom
+    case(
+        "concave",
+        [(40., 60.), (64., 72.), (88., 60.), (64., 52.)],
+        Emotion::Auto,
+        true,
+    ),
+    case(
+        "smiling",
+        [(40., 60.), (64., 50.), (88., 60.), (64., 80.)],
+        Emotion::Smile,
+        true,
+    ),
+    case(
+        "crying",
+        [(40., 60.), (64., 40.), (88., 60.), (64., 70.)],
+        Emotion::Cry,
+        true,
+    ),
+    case(
+        "crying_auto",
+        [(40., 60.), (64., 40.), (88., 60.), (64., 70.)],
+        Emotion::Auto,
+        true,
+    ),
+    case(
+        "degenerate_coincident",
+        [(64., 64.), (64., 64.), (64., 64.), (64., 64.)],
+        Emotion::Auto,
+        false,
+    ),
+    case(
+        "degenerate_collinear",
+        [(40., 60.), (56., 60.), (72., 60.), (88., 60.)],
+        Emotion::Auto,
+        false,
+    ),
+];
+
+fn project(case: &Case, blending: Blending) -> ciya_lib::errors::Result<DynamicImage> {
+    let [p1, p2, p3, p4] = case.control_points;
+    let control_points = ControlPoints::new(
+        Point::new(p1.0, p1.1),
+        Point::new(p2.0, p2.1),
+        Point::new(p3.0, p3.1),
+        Point::new(p4.0, p4.1),
+    );
+    let canvas = DynamicImage::ImageRgba8(RgbaImage::from_pixel(128, 128, Rgba([255; 4])));
+    Projector::new().with_blending(blending).project(
+        canvas,
+        control_points,
+        case.emotion,
+        ANTIALIAS_SCALE,
+    )
+}
+
+fn run(case: &Case, update: bool) -> Result<(), String> {
+    let name = format!("projector_{}", case.name);
+    match (project(case, case.blending), case.valid) {
+        (Ok(image), true) => {
+            common::check_golden(&name, &image, update)?;
+            // blended like the old warp, so that only the warp differs
+            let image = project(case, Blending::Srgb).map_err(|e| e.to_string())?;
+            common::check_similar(
+                &format!("warp_before/{}", name),
+                &image,
+                WARP_BEFORE_TOLERANCE,
+            )
+        }
+        (Err(Error::MathError(_)), false) => Ok(()),
+        (Ok(_), false) => Err(String::from("expected a math error")),
+        (Err(e), _) => Err(format!("unexpected error: {}", e)),
+    }
+}
+
+// The subset of libtest's command line which test runners and IDEs use to
+// list and run tests one by one.
+#[derive(Default)]
+struct Args {
+    list: bool,
+    ignored: bool,
+    exact: bool,
+    update: bool,
+    filters: Vec<String>,
+    skip: Vec<String>,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut parsed = Self {
+            update: env::var_os(common::UPDATE_GOLDEN_ENV).is_some(),
+            ..Self::default()
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--list" => parsed.list = true,
+                "--ignored" => parsed.ignored = true,
+                "--exact" => parsed.exact = true,
+                "--update-golden" => parsed.update = true,
+                "--skip" => parsed.skip.extend(args.next()),
+                // options taking a value which don't matter here
+                "--format" | "--logfile" | "--test-threads" | "--color" | "-Z" => {
+                    args.next();
+                }
+                // like libtest, free arguments filter cases by name
+                _ if !arg.starts_with('-') => parsed.filters.push(arg),
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    fn selects(&self, case: &Case) -> bool {
+        let matches = |filter: &String| {
+            if self.exact {
+                case.name == filter
+            } else {
+                case.name.contains(filter.as_str())
+            }
+        };
+        // none of the cases are ignored
+        !self.ignored
+            && (self.filters.is_empty() || self.filters.iter().any(matches))
+            && !self.skip.iter().any(matches)
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let cases = CASES.iter().filter(|case| args.selects(case));
+
+    if args.list {
+        for case in cases {
+            println!("{}: test", case.name);
+        }
+        return;
+    }
+
+    let mut failed = 0;
+    for case in cases {
+        match run(case, args.update) {
+            Ok(()) => println!("test {} ... ok", case.name),
+            Err(e) => {
+                println!("test {} ... FAILED\n  {}", case.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!("\n{} projector cases failed", failed);
+        process::exit(1);
+    }
+}
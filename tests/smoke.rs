@@ -1,67 +1,90 @@
 use std::{
-    fs::File,
-    io::{Cursor, Write},
+    fs,
+    io::Cursor,
     path::{Path, PathBuf},
 };
 
 use ciya_lib::{
     ciyafier::{Ciyafier, Emotion},
-    detectors::WeebDetector,
+    detectors::{MouthDetectorTrait, WeebDetector},
+    models::{ModelManager, FACE_MODEL, LANDMARK_MODEL},
 };
-use image::{io::Reader as ImageReader, ImageFormat};
-use reqwest::blocking::Client;
-use tempfile::tempdir;
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
 
 const TEST_IMAGE: &[u8] = include_bytes!("test.png");
 
-const FACE_MODEL_URL: &str = "https://raw.githubusercontent.com/nagadomi/lbpcascade_animeface/master/lbpcascade_animeface.xml";
-const LANDMARK_MODEL_URL: &str =
-    "https://github.com/PhotonQuantum/ciya-rs/releases/download/v0.0.1/anime_face_landmark.onnx";
+// The face model is vendored. The landmark model is taken from `resources/`
+// (see `make fetch-models`) or the model directory, and never downloaded.
+fn models() -> (PathBuf, PathBuf) {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let face_model = resources.join(FACE_MODEL.file_name);
+    let landmark_model = resources.join(LANDMARK_MODEL.file_name);
+    if landmark_model.is_file() {
+        return (face_model, landmark_model);
+    }
+    let landmark_model = ModelManager::with_default_root()
+        .and_then(|manager| manager.offline(true).ensure(&LANDMARK_MODEL))
+        .expect("landmark model not available, see `make fetch-models`");
+    (face_model, landmark_model)
+}
 
-#[test]
-fn smoke_test() {
-    let (face_model, landmark_model) = ensure_models();
-    let detector = Box::new(
-        WeebDetector::new(
-            face_model.to_str().unwrap(),
-            landmark_model.to_str().unwrap(),
-        )
-        .unwrap(),
-    );
-    let ciyafier = Ciyafier::new(detector);
-    let image = ImageReader::with_format(Cursor::new(TEST_IMAGE), ImageFormat::Png)
+fn test_image() -> DynamicImage {
+    ImageReader::with_format(Cursor::new(TEST_IMAGE), ImageFormat::Png)
         .decode()
-        .unwrap();
-    let _image = ciyafier.ciya(image, Emotion::Auto, 8).unwrap();
+        .unwrap()
 }
 
-fn ensure_models() -> (PathBuf, PathBuf) {
-    let data_path = tempdir().unwrap().into_path();
-    let download_path = (
-        data_path.join("ciya-rs").join("lbpcascade_animeface.xml"),
-        data_path.join("ciya-rs").join("anime_face_landmark.onnx"),
-    );
+#[test]
+#[ignore = "needs the landmark model, see `make fetch-models`"]
+fn smoke_test() {
+    let (face_model, landmark_model) = models();
+    let detector = WeebDetector::new(face_model, landmark_model).unwrap();
+    let image = test_image();
 
-    let http = Client::new();
-    if !(download_path.0.is_file()) {
-        let face_model = http.get(FACE_MODEL_URL).send().unwrap();
-        ensure_dir(download_path.0.parent().unwrap());
-        let mut file = File::create(&download_path.0).unwrap();
-        file.write_all(&face_model.bytes().unwrap()).unwrap();
-    }
-    if !(download_path.1.is_file()) {
-        let landmark_model = http.get(LANDMARK_MODEL_URL).send().unwrap();
-        ensure_dir(download_path.1.parent().unwrap());
-        let mut file = File::create(&download_path.1).unwrap();
-        file.write_all(&landmark_model.bytes().unwrap()).unwrap();
+    let control_points = detector.detect(&image).unwrap();
+    assert!(!control_points.is_irregular());
+    for point in [
+        control_points.p1,
+        control_points.p2,
+        control_points.p3,
+        control_points.p4,
+    ] {
+        assert!((0. ..image.width() as f32).contains(&point.x));
+        assert!((0. ..image.height() as f32).contains(&point.y));
     }
-    download_path
+
+    let ciyafier = Ciyafier::new(Box::new(detector));
+    let ciyaified = ciyafier.ciya(image, Emotion::Auto, 8).unwrap();
+    assert_eq!(
+        (ciyaified.width(), ciyaified.height()),
+        (test_image().width(), test_image().height())
+    );
 }
 
-fn ensure_dir(path: &Path) {
-    if path.is_file() {
-        panic!("Is a file");
-    } else if !path.exists() {
-        std::fs::create_dir_all(path).unwrap();
-    }
+#[test]
+#[ignore = "needs the landmark model, see `make fetch-models`"]
+fn detector_from_memory() {
+    let (face_model, landmark_model) = models();
+    let from_paths = WeebDetector::new(&face_model, &landmark_model).unwrap();
+    let (face_model, landmark_model) = (
+        fs::read(face_model).unwrap(),
+        fs::read(landmark_model).unwrap(),
+    );
+    let image = test_image();
+
+    let from_bytes = WeebDetector::from_bytes(&face_model, &landmark_model).unwrap();
+    let from_readers = WeebDetector::from_readers(
+        Cursor::new(face_model.as_slice()),
+        Cursor::new(landmark_model.as_slice()),
+    )
+    .unwrap();
+    let expected = format!("{:?}", from_paths.detect(&image).unwrap());
+    assert_eq!(
+        format!("{:?}", from_bytes.detect(&image).unwrap()),
+        expected
+    );
+    assert_eq!(
+        format!("{:?}", from_readers.detect(&image).unwrap()),
+        expected
+    );
 }
@@ -0,0 +1,186 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use reqwest::blocking::Client;
+use tempfile::tempdir;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const SECRET: &str = "test-secret";
+
+const ME: &str = r#"{"id":1,"is_bot":true,"first_name":"ciya","username":"ciya_bot","can_join_groups":true,"can_read_all_group_messages":false,"supports_inline_queries":true}"#;
+const SENT_MESSAGE: &str = r#"{"message_id":2,"date":0,"chat":{"id":42,"type":"private","first_name":"Test"},"text":"ok"}"#;
+const HELP_UPDATE: &str = r#"{"update_id":1,"message":{"message_id":1,"date":0,"chat":{"id":42,"type":"private","first_name":"Test"},"from":{"id":42,"is_bot":false,"first_name":"Test"},"text":"/help","entities":[{"type":"bot_command","offset":0,"length":5}]}}"#;
+
+struct BotProcess(Child);
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn webhook_updates_are_answered() {
+    let (api_url, requests) = fake_api();
+    let port = free_port();
+    let metrics_port = free_port();
+    let dir = tempdir().unwrap();
+    let webhook_url = format!("http://127.0.0.1:{}/webhook", port);
+    let model_dir = dir.path().join("models");
+
+    let _bot = BotProcess(
+        Command::new(env!("CARGO_BIN_EXE_ciya_bot"))
+            .current_dir(dir.path())
+            .env_remove("CIYA_BOT_CONFIG")
+            .env_remove("CIYA_BOT_FACE_MODEL")
+            .env_remove("CIYA_BOT_LANDMARK_MODEL")
+            .env("TELOXIDE_TOKEN", "1234:TEST")
+            .env("CIYA_BOT_DB", dir.path().join("bot.sled"))
+            .env("CIYA_BOT_API_URL", &api_url)
+            .env("CIYA_BOT_WEBHOOK_URL", &webhook_url)
+            .env("CIYA_BOT_LISTEN", format!("127.0.0.1:{}", port))
+            .env("CIYA_BOT_WEBHOOK_SECRET", SECRET)
+            .env(
+                "CIYA_BOT_METRICS_LISTEN",
+                format!("127.0.0.1:{}", metrics_port),
+            )
+            // Keep the models unavailable instead of downloading them.
+            .env("CIYA_BOT_OFFLINE", "true")
+            .env("CIYA_BOT_MODEL_DIR", &model_dir)
+            .spawn()
+            .unwrap(),
+    );
+
+    let webhook = wait_for(&requests, "setwebhook");
+    assert!(webhook.contains(&webhook_url));
+    assert!(webhook.contains(SECRET));
+
+    let http = Client::new();
+    let post = |secret: &str| {
+        http.post(&webhook_url)
+            .header("Content-Type", "application/json")
+            .header("X-Telegram-Bot-Api-Secret-Token", secret)
+            .body(HELP_UPDATE)
+            .send()
+            .unwrap()
+            .status()
+    };
+    assert_eq!(post("wrong-secret").as_u16(), 401);
+    assert!(post(SECRET).is_success());
+
+    let reply = wait_for(&requests, "sendmessage");
+    assert!(reply.contains(r#""chat_id":42"#));
+
+    // The update was answered even though the models couldn't be loaded.
+    if !cfg!(feature = "embed-models") {
+        let health = http
+            .get(format!("http://127.0.0.1:{}/healthz", metrics_port))
+            .send()
+            .unwrap();
+        assert_eq!(health.status().as_u16(), 503);
+    }
+    let downloaded = model_dir
+        .read_dir()
+        .map(|entries| {
+            entries
+                .flatten()
+                .any(|e| e.path().extension() == Some("onnx".as_ref()))
+        })
+        .unwrap_or(false);
+    assert!(!downloaded);
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// Wait for a request to the given Bot API method, returning its body.
+fn wait_for(requests: &Receiver<(String, String)>, method: &str) -> String {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (name, body) = requests
+            .recv_timeout(timeout)
+            .unwrap_or_else(|_| panic!("bot didn't call {}", method));
+        if name == method {
+            return body;
+        }
+    }
+}
+
+// Start a fake Bot API server, returning its url and the requests it receives.
+fn fake_api() -> (String, Receiver<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            thread::spawn(move || serve(stream, &tx));
+        }
+    });
+    (url, rx)
+}
+
+fn serve(stream: TcpStream, requests: &Sender<(String, String)>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let method = request_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|path| path.rsplit('/').next())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let result = match method.as_str() {
+            "getme" => ME,
+            "sendmessage" => SENT_MESSAGE,
+            "getupdates" => "[]",
+            _ => "true",
+        };
+        let response = format!(r#"{{"ok":true,"result":{}}}"#, result);
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        )
+        .unwrap();
+        writer.flush().unwrap();
+
+        let _ = requests.send((method, String::from_utf8_lossy(&body).into_owned()));
+    }
+}